@@ -0,0 +1,80 @@
+//! Compares running a program with and without the decode cache.
+//! Run with `cargo bench --bench decode_cache`.
+
+use dcpu16_rs::*;
+use std::time::{Duration, Instant};
+
+const CYCLES: usize = 20_000_000;
+const ROUNDS: usize = 10;
+
+/// Arithmetic, memory and subroutine calls in a tight loop
+const SOURCE: &str = "
+        SET SP, 0
+        SET I, 0
+:loop   ADD I, 1
+        SET A, I
+        MUL A, 3
+        SET [0x8000+I], A
+        AND I, 0xff
+        JSR sub
+        IFN I, 0
+            SET PC, loop
+        ADD [counter], 1
+        SET PC, loop
+:sub    SET B, [0x8000+I]
+        XOR B, 0x5555
+        SHR B, 2
+        SET PC, POP
+:counter DAT 0
+";
+
+fn run(program: &Program, cached: bool) -> (Duration, Processor) {
+    let mut machine = Processor::new();
+    machine.set_decode_cache(cached);
+    machine.memory_mut().load_program(0x0000, program);
+
+    let start = Instant::now();
+    for _ in 0..CYCLES {
+        machine.tick();
+    }
+    (start.elapsed(), machine)
+}
+
+fn main() {
+    let program = assemble(SOURCE).expect("benchmark program should assemble");
+
+    // Alternate between the two so they see the same conditions, and keep the fastest of each
+    let mut best = [Duration::from_secs(u64::MAX), Duration::from_secs(u64::MAX)];
+    for _ in 0..ROUNDS {
+        let (uncached, uncached_machine) = run(&program, false);
+        let (cached, cached_machine) = run(&program, true);
+        assert_eq!(
+            uncached_machine.memory().words(),
+            cached_machine.memory().words(),
+            "cached run should leave memory the same"
+        );
+        for &register in &Register::ALL {
+            assert_eq!(
+                uncached_machine.get_register(register),
+                cached_machine.get_register(register),
+                "cached run should leave {} the same",
+                register
+            );
+        }
+        best[0] = best[0].min(uncached);
+        best[1] = best[1].min(cached);
+    }
+
+    for (name, elapsed) in ["uncached", "cached"].iter().zip(&best) {
+        println!(
+            "{:<10} {:>8.2?} {:>6.2} ns/cycle",
+            name,
+            elapsed,
+            elapsed.as_nanos() as f64 / CYCLES as f64
+        );
+    }
+    println!(
+        "speedup    {:.2}x",
+        best[0].as_secs_f64() / best[1].as_secs_f64()
+    );
+}
@@ -0,0 +1,689 @@
+use super::opcodes::{self, SPL};
+use super::processor::Register;
+use super::program::Program;
+use super::value::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AssemblerErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    InvalidNumber(String),
+    UnknownInstruction(String),
+    UnknownLabel(String),
+    DuplicateLabel(String),
+    InvalidOperand(String),
+    ExpectedOperand,
+    UnexpectedToken(String),
+    ProgramTooLarge,
+}
+
+/// An error found while assembling, with the 1-based line and column it occurred at
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssemblerError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AssemblerErrorKind,
+}
+
+impl AssemblerError {
+    fn new(line: usize, column: usize, kind: AssemblerErrorKind) -> AssemblerError {
+        AssemblerError { line, column, kind }
+    }
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AssemblerErrorKind::*;
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c),
+            UnterminatedString => write!(f, "unterminated string"),
+            InvalidNumber(num) => write!(f, "invalid number '{}'", num),
+            UnknownInstruction(name) => write!(f, "unknown instruction '{}'", name),
+            UnknownLabel(name) => write!(f, "unknown label '{}'", name),
+            DuplicateLabel(name) => write!(f, "label '{}' is already defined", name),
+            InvalidOperand(reason) => write!(f, "invalid operand: {}", reason),
+            ExpectedOperand => write!(f, "expected an operand"),
+            UnexpectedToken(token) => write!(f, "unexpected '{}'", token),
+            ProgramTooLarge => write!(f, "program does not fit in memory"),
+        }
+    }
+}
+
+impl Error for AssemblerError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Label(String),
+    Ident(String),
+    Number(u16),
+    Str(String),
+    Comma,
+    OpenBracket,
+    CloseBracket,
+    Plus,
+    Minus,
+    Increment,
+    Decrement,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Label(name) => write!(f, ":{}", name),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Number(num) => write!(f, "{}", num),
+            Token::Str(string) => write!(f, "\"{}\"", string),
+            Token::Comma => write!(f, ","),
+            Token::OpenBracket => write!(f, "["),
+            Token::CloseBracket => write!(f, "]"),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Increment => write!(f, "++"),
+            Token::Decrement => write!(f, "--"),
+        }
+    }
+}
+
+/// A token and the column it started at
+#[derive(Clone, Debug)]
+struct Spanned {
+    token: Token,
+    column: usize,
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Parses a number the way the assembler reads literals: decimal, `0x` hexadecimal or `0b` binary
+pub fn parse_literal(text: &str) -> Option<u16> {
+    let lower = text.to_ascii_lowercase();
+    let value = if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()?
+    } else {
+        lower.parse::<u32>().ok()?
+    };
+
+    if value > 0xFFFF {
+        None
+    } else {
+        Some(value as u16)
+    }
+}
+
+fn tokenize(line: &str, line_number: usize) -> Result<Vec<Spanned>, AssemblerError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        let error = |kind| Err(AssemblerError::new(line_number, column, kind));
+
+        let token = match c {
+            ';' => break,
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ',' => Token::Comma,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            '+' if chars.get(i + 1) == Some(&'+') => {
+                i += 1;
+                Token::Increment
+            }
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                i += 1;
+                Token::Decrement
+            }
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            ':' => {
+                let start = i + 1;
+                while i + 1 < chars.len() && is_ident_char(chars[i + 1]) {
+                    i += 1;
+                }
+                if start > i {
+                    return error(AssemblerErrorKind::UnexpectedCharacter(':'));
+                }
+                Token::Label(chars[start..=i].iter().collect())
+            }
+            '"' => {
+                let start = i + 1;
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return error(AssemblerErrorKind::UnterminatedString);
+                }
+                Token::Str(chars[start..i].iter().collect())
+            }
+            '\'' => {
+                if chars.get(i + 2) != Some(&'\'') {
+                    return error(AssemblerErrorKind::UnterminatedString);
+                }
+                i += 2;
+                Token::Number(chars[i - 1] as u16)
+            }
+            _ if c.is_ascii_digit() => {
+                let start = i;
+                while i + 1 < chars.len() && is_ident_char(chars[i + 1]) {
+                    i += 1;
+                }
+                let text: String = chars[start..=i].iter().collect();
+                match parse_literal(&text) {
+                    Some(num) => Token::Number(num),
+                    None => return error(AssemblerErrorKind::InvalidNumber(text)),
+                }
+            }
+            _ if is_ident_char(c) => {
+                let start = i;
+                while i + 1 < chars.len() && is_ident_char(chars[i + 1]) {
+                    i += 1;
+                }
+                Token::Ident(chars[start..=i].iter().collect())
+            }
+            _ => return error(AssemblerErrorKind::UnexpectedCharacter(c)),
+        };
+
+        tokens.push(Spanned { token, column });
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+fn is_general_register(register: Register) -> bool {
+    (register as u16) < 0x08
+}
+
+/// A reference to a label that will be resolved once every label is known
+#[derive(Clone, Debug)]
+struct LabelRef {
+    name: String,
+    negative: bool,
+    line: usize,
+    column: usize,
+}
+
+/// A word whose value may depend on labels
+#[derive(Clone, Debug)]
+struct Expr {
+    value: u16,
+    labels: Vec<LabelRef>,
+}
+
+impl Expr {
+    fn constant(value: u16) -> Expr {
+        Expr {
+            value,
+            labels: vec![],
+        }
+    }
+}
+
+/// An encoded operand; `word` is the extra word that follows the instruction, if any
+struct Operand {
+    code: u16,
+    word: Option<Expr>,
+}
+
+impl Operand {
+    fn value(value: Value) -> Operand {
+        Operand {
+            code: value.to_u16(),
+            word: None,
+        }
+    }
+
+    fn with_word(value: Value, word: Expr) -> Operand {
+        Operand {
+            code: value.to_u16(),
+            word: Some(word),
+        }
+    }
+}
+
+/// Parses the tokens of a single line
+struct LineParser<'a> {
+    tokens: &'a [Spanned],
+    pos: usize,
+    line: usize,
+    end_column: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|spanned| &spanned.token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|spanned| spanned.column)
+            .unwrap_or(self.end_column)
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn error<T>(&self, kind: AssemblerErrorKind) -> Result<T, AssemblerError> {
+        Err(AssemblerError::new(self.line, self.column(), kind))
+    }
+
+    fn unexpected<T>(&self) -> Result<T, AssemblerError> {
+        match self.peek() {
+            Some(token) => self.error(AssemblerErrorKind::UnexpectedToken(token.to_string())),
+            None => self.error(AssemblerErrorKind::ExpectedOperand),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), AssemblerError> {
+        if self.peek() == Some(&expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.unexpected()
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), AssemblerError> {
+        if self.is_done() {
+            Ok(())
+        } else {
+            self.unexpected()
+        }
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(name)) => name.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    /// Parses a sum of numbers, labels and at most one register
+    fn parse_terms(&mut self) -> Result<(Option<Register>, Option<Expr>), AssemblerError> {
+        let mut register = None;
+        let mut expr: Option<Expr> = None;
+        let mut negative = false;
+
+        if self.peek() == Some(&Token::Minus) {
+            negative = true;
+            self.pos += 1;
+        }
+
+        loop {
+            let column = self.column();
+            match self.peek() {
+                Some(Token::Number(num)) => {
+                    let num = if negative { num.wrapping_neg() } else { *num };
+                    let expr = expr.get_or_insert(Expr::constant(0));
+                    expr.value = expr.value.wrapping_add(num);
+                }
+                Some(Token::Ident(name)) => {
+                    // IA can only be reached through IAG and IAS
+                    let reg = Register::from_name(name).filter(|&reg| reg != Register::IA);
+                    if let Some(reg) = reg {
+                        if negative || register.is_some() {
+                            return self.error(AssemblerErrorKind::InvalidOperand(format!(
+                                "can't use {} here",
+                                name
+                            )));
+                        }
+                        register = Some(reg);
+                    } else {
+                        let expr = expr.get_or_insert(Expr::constant(0));
+                        expr.labels.push(LabelRef {
+                            name: name.clone(),
+                            negative,
+                            line: self.line,
+                            column,
+                        });
+                    }
+                }
+                _ => return self.unexpected(),
+            }
+            self.pos += 1;
+
+            match self.peek() {
+                Some(Token::Plus) => negative = false,
+                Some(Token::Minus) => negative = true,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+
+        Ok((register, expr))
+    }
+
+    fn parse_operand(&mut self, is_a: bool) -> Result<Operand, AssemblerError> {
+        if self.peek_keyword("PUSH") {
+            if is_a {
+                return self.error(AssemblerErrorKind::InvalidOperand(
+                    "PUSH can only be used as b".to_owned(),
+                ));
+            }
+            self.pos += 1;
+            return Ok(Operand::value(Value::Push));
+        }
+        if self.peek_keyword("POP") {
+            if !is_a {
+                return self.error(AssemblerErrorKind::InvalidOperand(
+                    "POP can only be used as a".to_owned(),
+                ));
+            }
+            self.pos += 1;
+            return Ok(Operand::value(Value::Pop));
+        }
+        if self.peek_keyword("PEEK") {
+            self.pos += 1;
+            return Ok(Operand::value(Value::Peek));
+        }
+        if self.peek_keyword("PICK") {
+            self.pos += 1;
+            let (register, expr) = self.parse_terms()?;
+            return match (register, expr) {
+                (None, Some(expr)) => Ok(Operand::with_word(Value::Pick, expr)),
+                _ => self.error(AssemblerErrorKind::InvalidOperand(
+                    "PICK takes a number".to_owned(),
+                )),
+            };
+        }
+
+        if self.peek() == Some(&Token::OpenBracket) {
+            self.pos += 1;
+            let operand = self.parse_pointer(is_a)?;
+            self.expect(Token::CloseBracket)?;
+            return Ok(operand);
+        }
+
+        let column = self.column();
+        match self.parse_terms()? {
+            (Some(reg), None) => Ok(Operand::value(Value::Register(reg))),
+            (None, Some(expr)) => {
+                let is_short =
+                    expr.labels.is_empty() && (expr.value <= 0x1E || expr.value == 0xFFFF);
+                if is_a && is_short {
+                    Ok(Operand {
+                        code: expr.value.wrapping_add(0x21),
+                        word: None,
+                    })
+                } else {
+                    Ok(Operand::with_word(Value::NextWord, expr))
+                }
+            }
+            _ => Err(AssemblerError::new(
+                self.line,
+                column,
+                AssemblerErrorKind::InvalidOperand("can't add a register to a literal".to_owned()),
+            )),
+        }
+    }
+
+    /// Parses the inside of `[...]`
+    fn parse_pointer(&mut self, is_a: bool) -> Result<Operand, AssemblerError> {
+        let column = self.column();
+
+        // [--SP] and [SP++]
+        if self.peek() == Some(&Token::Decrement) {
+            self.pos += 1;
+            if !self.peek_keyword("SP") || is_a {
+                return self.error(AssemblerErrorKind::InvalidOperand(
+                    "[--SP] can only be used as b".to_owned(),
+                ));
+            }
+            self.pos += 1;
+            return Ok(Operand::value(Value::Push));
+        }
+        if self.peek_keyword("SP")
+            && self.tokens.get(self.pos + 1).map(|s| &s.token) == Some(&Token::Increment)
+        {
+            if !is_a {
+                return self.error(AssemblerErrorKind::InvalidOperand(
+                    "[SP++] can only be used as a".to_owned(),
+                ));
+            }
+            self.pos += 2;
+            return Ok(Operand::value(Value::Pop));
+        }
+
+        let line = self.line;
+        let invalid = |reason: &str| {
+            Err(AssemblerError::new(
+                line,
+                column,
+                AssemblerErrorKind::InvalidOperand(reason.to_owned()),
+            ))
+        };
+        match self.parse_terms()? {
+            (Some(Register::SP), None) => Ok(Operand::value(Value::Peek)),
+            (Some(Register::SP), Some(expr)) => Ok(Operand::with_word(Value::Pick, expr)),
+            (Some(reg), None) if is_general_register(reg) => {
+                Ok(Operand::value(Value::RegisterPointer(reg)))
+            }
+            (Some(reg), Some(expr)) if is_general_register(reg) => {
+                Ok(Operand::with_word(Value::RegisterPointerOffset(reg), expr))
+            }
+            (Some(reg), _) => invalid(&format!("can't use {:?} as a pointer", reg)),
+            (None, Some(expr)) => Ok(Operand::with_word(Value::NextWordPointer, expr)),
+            (None, None) => invalid("empty pointer"),
+        }
+    }
+
+    /// Parses the comma separated values of a `DAT`, returning whether it ended with a comma
+    fn parse_data(&mut self, words: &mut Vec<Expr>) -> Result<bool, AssemblerError> {
+        while !self.is_done() {
+            if let Some(Token::Str(string)) = self.peek() {
+                words.extend(string.chars().map(|c| Expr::constant(c as u16)));
+                self.pos += 1;
+            } else {
+                match self.parse_terms()? {
+                    (None, Some(expr)) => words.push(expr),
+                    _ => {
+                        return self.error(AssemblerErrorKind::InvalidOperand(
+                            "DAT can't contain registers".to_owned(),
+                        ))
+                    }
+                }
+            }
+
+            if self.is_done() {
+                return Ok(false);
+            }
+            self.expect(Token::Comma)?;
+        }
+
+        Ok(true)
+    }
+}
+
+/// Assembles Notch-style DCPU-16 assembly source into a `Program`
+///
+/// Label references are always encoded with an extra word, so the size of every instruction is
+/// known before labels are resolved.
+pub struct Assembler {
+    labels: HashMap<String, u16>,
+    lines: BTreeMap<u16, usize>,
+}
+
+impl Default for Assembler {
+    fn default() -> Assembler {
+        Assembler::new()
+    }
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler {
+            labels: HashMap::new(),
+            lines: BTreeMap::new(),
+        }
+    }
+
+    pub fn assemble(&mut self, source: &str) -> Result<Program, AssemblerError> {
+        self.labels.clear();
+        self.lines.clear();
+
+        let mut words: Vec<Expr> = Vec::with_capacity(64);
+        // Set after a `DAT` that has no values yet or ends in a comma
+        let mut continues_data = false;
+
+        for (i, text) in source.lines().enumerate() {
+            let line = i + 1;
+            let tokens = tokenize(text, line)?;
+            let mut parser = LineParser {
+                tokens: &tokens,
+                pos: 0,
+                line,
+                end_column: text.chars().count() + 1,
+            };
+
+            while let Some(Token::Label(name)) = parser.peek() {
+                if words.len() > 0xFFFF {
+                    return parser.error(AssemblerErrorKind::ProgramTooLarge);
+                }
+                if self
+                    .labels
+                    .insert(name.clone(), words.len() as u16)
+                    .is_some()
+                {
+                    return parser.error(AssemblerErrorKind::DuplicateLabel(name.clone()));
+                }
+                continues_data = false;
+                parser.pos += 1;
+            }
+
+            if parser.is_done() {
+                continue;
+            }
+
+            let start = words.len();
+            if start <= 0xFFFF {
+                self.lines.insert(start as u16, line);
+            }
+            let column = parser.column();
+            if continues_data {
+                continues_data = parser.parse_data(&mut words)?;
+            } else {
+                let mnemonic = match parser.peek() {
+                    Some(Token::Ident(name)) => name.clone(),
+                    _ => return parser.unexpected(),
+                };
+                parser.pos += 1;
+
+                if mnemonic.eq_ignore_ascii_case("DAT") {
+                    continues_data = parser.is_done();
+                    continues_data |= parser.parse_data(&mut words)?;
+                } else if let Some(op) = opcodes::basic_opcode(&mnemonic) {
+                    let b = parser.parse_operand(false)?;
+                    parser.expect(Token::Comma)?;
+                    let a = parser.parse_operand(true)?;
+                    parser.expect_end()?;
+                    Self::push_instruction(&mut words, op | b.code << 5 | a.code << 10, a, b);
+                } else if let Some(op) = opcodes::special_opcode(&mnemonic) {
+                    let a = parser.parse_operand(true)?;
+                    parser.expect_end()?;
+                    let b = Operand::value(Value::OpCode(op));
+                    Self::push_instruction(&mut words, SPL | b.code << 5 | a.code << 10, a, b);
+                } else {
+                    return Err(AssemblerError::new(
+                        line,
+                        column,
+                        AssemblerErrorKind::UnknownInstruction(mnemonic),
+                    ));
+                }
+            }
+
+            if words.len() > 0x10000 {
+                return Err(AssemblerError::new(
+                    line,
+                    column,
+                    AssemblerErrorKind::ProgramTooLarge,
+                ));
+            }
+        }
+
+        let mut program = Program::new();
+        for expr in &words {
+            program.add_word(self.resolve(expr)?);
+        }
+
+        Ok(program)
+    }
+
+    fn push_instruction(words: &mut Vec<Expr>, word: u16, a: Operand, b: Operand) {
+        words.push(Expr::constant(word));
+        // The extra word for `a` comes before `b`'s
+        words.extend(a.word);
+        words.extend(b.word);
+    }
+
+    fn resolve(&self, expr: &Expr) -> Result<u16, AssemblerError> {
+        let mut value = expr.value;
+        for label in &expr.labels {
+            let addr = match self.labels.get(&label.name) {
+                Some(&addr) => addr,
+                None => {
+                    return Err(AssemblerError::new(
+                        label.line,
+                        label.column,
+                        AssemblerErrorKind::UnknownLabel(label.name.clone()),
+                    ))
+                }
+            };
+            value = if label.negative {
+                value.wrapping_sub(addr)
+            } else {
+                value.wrapping_add(addr)
+            };
+        }
+
+        Ok(value)
+    }
+
+    /// All labels found in the last assembled source and their addresses
+    pub fn labels(&self) -> &HashMap<String, u16> {
+        &self.labels
+    }
+
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.get(name).cloned()
+    }
+
+    /// The 1-based source line of each instruction and line of data in the last assembled source,
+    /// by address
+    pub fn lines(&self) -> &BTreeMap<u16, usize> {
+        &self.lines
+    }
+
+    /// The source line that assembled into the word at `addr`
+    pub fn line_at(&self, addr: u16) -> Option<usize> {
+        self.lines.range(..=addr).next_back().map(|(_, &line)| line)
+    }
+
+    /// Address of the first instruction or data on `line` or, if it has none, the next line
+    /// that does. Returns the line actually used along with the address.
+    pub fn addr_of_line(&self, line: usize) -> Option<(u16, usize)> {
+        self.lines
+            .iter()
+            .filter(|&(_, &other)| other >= line)
+            .min_by_key(|&(&addr, &other)| (other, addr))
+            .map(|(&addr, &other)| (addr, other))
+    }
+}
+
+/// Assembles `source` into a `Program`
+pub fn assemble(source: &str) -> Result<Program, AssemblerError> {
+    Assembler::new().assemble(source)
+}
@@ -0,0 +1,584 @@
+use dcpu16_rs::*;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+const USAGE: &str = "Usage: dcpu [--little-endian] [--base ADDR] [--gdb PORT | --gdb-stdio] \
+                     <image.bin|source.dasm>\n       dcpu --dap";
+
+/// Appends a line to a command's output
+macro_rules! say {
+    ($out:expr, $($arg:tt)*) => {{
+        $out.push_str(&format!($($arg)*));
+        $out.push('\n');
+    }};
+}
+
+const HELP: &str = "\
+Commands:
+  step [n]              Execute the next n instructions (s)
+  back [n]              Undo the last n instructions
+  rewind <addr|label>   Run backwards until PC reaches addr
+  continue [cycles]     Run until a breakpoint, an error, or the cycle limit (c)
+  break <addr|label>    Set a breakpoint (b)
+  delete <addr|label>   Remove a breakpoint (d)
+  breakpoints           List breakpoints
+  watch <addr> [end] [r|w|rw]
+                        Stop when memory in addr..=end is accessed (w)
+  watch <reg>           Stop when a register changes
+  unwatch <addr|reg>    Remove watchpoints on an address or register
+  watchpoints           List watchpoints
+  registers             Show registers (r)
+  set <reg|addr> <val>  Set a register or memory word
+  memory <addr> [n]     Show n words of memory (m)
+  disassemble [addr] [n]
+                        Disassemble n instructions, around PC by default (x)
+  interrupts            Show the interrupt queue (i)
+  hardware              List attached hardware (hw)
+  trace [file [binary]] Trace executed instructions to stdout or a file
+  trace off             Stop tracing
+  save <file>           Save a snapshot of the whole machine
+  load <file>           Restore a snapshot saved with 'save'
+  help                  Show this message (h)
+  quit                  Exit (q)";
+
+/// Cycles `continue` will run before giving up, so infinite loops don't hang the debugger
+const DEFAULT_CYCLE_LIMIT: usize = 10_000_000;
+
+/// Commands return their output as text, so the debugger can be driven by any reader and writer
+struct Debugger {
+    machine: Processor,
+    labels: HashMap<String, u16>,
+}
+
+fn hardware_name(id: u32) -> &'static str {
+    match id {
+        0x7349F615 => "LEM1802 monitor",
+        0x12D0B402 => "Generic clock",
+        0x30CF7406 => "Generic keyboard",
+        _ => "Unknown device",
+    }
+}
+
+impl Debugger {
+    fn new(machine: Processor, labels: HashMap<String, u16>) -> Debugger {
+        Debugger { machine, labels }
+    }
+
+    /// Loads a binary image, or assembles `.dasm` source, at `base` and points PC at it
+    fn load(
+        mut machine: Processor,
+        path: &str,
+        base: u16,
+        endianness: Endianness,
+    ) -> Result<Debugger, String> {
+        let assembler = machine
+            .memory_mut()
+            .load_program_file(base, path, endianness)
+            .map_err(|err| format!("{}: {}", path, err))?;
+        let labels = assembler.map(|assembler| assembler.labels().clone());
+        machine.set_register(Register::PC, base);
+        machine.enable_journal(DEFAULT_JOURNAL_CAPACITY);
+
+        Ok(Debugger::new(machine, labels.unwrap_or_default()))
+    }
+
+    fn parse_address(&self, arg: Option<&str>) -> Result<u16, String> {
+        let arg = arg.ok_or("Expected an address or label")?;
+        if let Some(&addr) = self.labels.get(arg) {
+            return Ok(addr);
+        }
+        if let Some(register) = Register::from_name(arg) {
+            return Ok(self.machine.get_register(register));
+        }
+        parse_literal(arg).ok_or_else(|| format!("Unknown address or label '{}'", arg))
+    }
+
+    fn label_at(&self, addr: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|&(_, &label_addr)| label_addr == addr)
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn step(&mut self, count: usize, out: &mut String) {
+        for _ in 0..count {
+            if let Err(err) = self.machine.step_instruction() {
+                say!(out, "Error: {}", err);
+                break;
+            }
+        }
+        self.show_current(out);
+    }
+
+    fn continue_execution(&mut self, limit: usize, out: &mut String) {
+        match self.machine.run_until(limit) {
+            StopReason::Breakpoint(pc) => say!(out, "Breakpoint at {:#06x}", pc),
+            StopReason::MemoryRead { pc, addr, value } => {
+                say!(out, "{:#06x} read {:#06x} from {:#06x}", pc, value, addr)
+            }
+            StopReason::MemoryWrite { pc, addr, old, new } => say!(
+                out,
+                "{:#06x} wrote {:#06x} to {:#06x} (was {:#06x})",
+                pc,
+                new,
+                addr,
+                old
+            ),
+            StopReason::RegisterChanged { register, old, new } => {
+                say!(
+                    out,
+                    "{} changed from {:#06x} to {:#06x}",
+                    register,
+                    old,
+                    new
+                )
+            }
+            StopReason::CycleLimit => say!(out, "Stopped after {} cycles", limit),
+            StopReason::Error(err) => say!(out, "Error: {}", err),
+        }
+        self.show_current(out);
+    }
+
+    fn watch(&mut self, args: &[&str], out: &mut String) -> Result<(), String> {
+        if let [name] = args {
+            if let Some(register) = Register::from_name(name) {
+                self.machine.watch_register(register);
+                say!(out, "Watching {}", register);
+                return Ok(());
+            }
+        }
+
+        let start = self.parse_address(args.first().cloned())?;
+        let mut end = start;
+        let mut kind = WatchKind::Write;
+        for &arg in args.iter().skip(1) {
+            match arg {
+                "r" => kind = WatchKind::Read,
+                "w" => kind = WatchKind::Write,
+                "rw" => kind = WatchKind::ReadWrite,
+                _ => end = self.parse_address(Some(arg))?,
+            }
+        }
+        if end < start {
+            return Err(format!("Invalid range {:#06x}..={:#06x}", start, end));
+        }
+        self.machine.add_watchpoint(start..=end, kind);
+        say!(out, "Watching {:#06x}..={:#06x} ({:?})", start, end, kind);
+        Ok(())
+    }
+
+    fn unwatch(&mut self, arg: Option<&str>) -> Result<(), String> {
+        let arg = arg.ok_or("Expected an address or register")?;
+        if let Some(register) = Register::from_name(arg) {
+            if !self.machine.unwatch_register(register) {
+                return Err(format!("{} isn't being watched", register));
+            }
+        } else {
+            let addr = self.parse_address(Some(arg))?;
+            if !self.machine.remove_watchpoint(addr) {
+                return Err(format!("No watchpoint at {:#06x}", addr));
+            }
+        }
+        Ok(())
+    }
+
+    fn show_watchpoints(&self, out: &mut String) {
+        for watch in self.machine.watchpoints() {
+            say!(
+                out,
+                "{:#06x}..={:#06x} ({:?})",
+                watch.start,
+                watch.end,
+                watch.kind
+            );
+        }
+        for register in self.machine.watched_registers() {
+            say!(out, "{}", register);
+        }
+    }
+
+    fn show_current(&self, out: &mut String) {
+        let pc = self.machine.get_register(Register::PC);
+        if let Some(line) = self.machine.disassemble(pc, 1).first() {
+            say!(out, "=> {}", line);
+        }
+    }
+
+    fn show_registers(&self, out: &mut String) {
+        let registers: Vec<String> = Register::ALL
+            .iter()
+            .map(|&register| format!("{}={:04x}", register, self.machine.get_register(register)))
+            .collect();
+        say!(out, "{}", registers[..8].join(" "));
+        say!(out, "{}", registers[8..].join(" "));
+        say!(
+            out,
+            "cycle={} wait={}",
+            self.machine.cycle(),
+            self.machine.cycle_wait()
+        );
+    }
+
+    fn show_memory(&self, addr: u16, count: usize, out: &mut String) {
+        for row in 0..count.div_ceil(8) {
+            let start = addr.wrapping_add(row as u16 * 8);
+            let words: Vec<String> = (0..8.min(count - row * 8))
+                .map(|i| {
+                    format!(
+                        "{:04x}",
+                        self.machine.get_memory(start.wrapping_add(i as u16))
+                    )
+                })
+                .collect();
+            say!(out, "{:04x}: {}", start, words.join(" "));
+        }
+    }
+
+    /// Finds an address a few instructions before `pc` that disassembles cleanly up to `pc`
+    fn disassembly_start(&self, pc: u16) -> u16 {
+        for back in (1..=pc.min(6)).rev() {
+            let start = pc - back;
+            let mut addr = start;
+            for line in self.machine.disassemble(start, back as usize) {
+                addr = addr.wrapping_add(line.words.len() as u16);
+                if addr == pc {
+                    return start;
+                }
+            }
+        }
+        pc
+    }
+
+    fn show_disassembly(&self, addr: Option<u16>, count: usize, out: &mut String) {
+        let pc = self.machine.get_register(Register::PC);
+        let start = addr.unwrap_or_else(|| self.disassembly_start(pc));
+        for line in self.machine.disassemble(start, count) {
+            if let Some(label) = self.label_at(line.addr) {
+                say!(out, ":{}", label);
+            }
+            let marker = if line.addr == pc { "=>" } else { "  " };
+            let breakpoint = if self.machine.breakpoints().contains(&line.addr) {
+                "*"
+            } else {
+                " "
+            };
+            say!(out, "{}{} {}", breakpoint, marker, line);
+        }
+    }
+
+    fn show_interrupts(&self, out: &mut String) {
+        say!(
+            out,
+            "IA={:04x} queuing={}",
+            self.machine.get_register(Register::IA),
+            self.machine.is_queuing_interrupts()
+        );
+        let queue = self.machine.interrupt_queue();
+        if queue.is_empty() {
+            say!(out, "Interrupt queue is empty");
+        }
+        for (i, message) in queue.iter().enumerate() {
+            say!(out, "{:3}: {:#06x}", i, message);
+        }
+    }
+
+    fn show_hardware(&self, out: &mut String) {
+        for index in 0..self.machine.hardware_count() {
+            let rc = match self.machine.get_hardware(index) {
+                Some(rc) => rc,
+                None => continue,
+            };
+            let hardware = match rc.try_borrow() {
+                Ok(hardware) => hardware,
+                Err(_) => {
+                    say!(out, "{:3}: busy", index);
+                    continue;
+                }
+            };
+            say!(
+                out,
+                "{:3}: {:#010x} version {:#06x} manufacturer {:#010x} ({})",
+                index,
+                hardware.id(),
+                hardware.version(),
+                hardware.manufacturer(),
+                hardware_name(hardware.id())
+            );
+        }
+    }
+
+    fn set(&mut self, target: Option<&str>, value: Option<&str>) -> Result<(), String> {
+        let target = target.ok_or("Expected a register or address")?;
+        let value = self.parse_address(value)?;
+        if let Some(register) = Register::from_name(target) {
+            self.machine.set_register(register, value);
+        } else {
+            let addr = self.parse_address(Some(target))?;
+            self.machine.set_memory(addr, value);
+        }
+        Ok(())
+    }
+
+    /// Runs a single command, appending what it prints to `out`. Returns false when the debugger
+    /// should exit.
+    fn execute(&mut self, line: &str, out: &mut String) -> Result<bool, String> {
+        let mut args = line.split_whitespace();
+        let command = match args.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let count = |arg: Option<&str>, default: usize| -> Result<usize, String> {
+            match arg {
+                Some(arg) => arg.parse().map_err(|_| format!("Invalid count '{}'", arg)),
+                None => Ok(default),
+            }
+        };
+
+        match command {
+            "s" | "step" => {
+                let n = count(args.next(), 1)?;
+                self.step(n, out);
+            }
+            "back" => {
+                let n = count(args.next(), 1)?;
+                for _ in 0..n {
+                    if !self.machine.step_back() {
+                        say!(out, "No more history");
+                        break;
+                    }
+                }
+                self.show_current(out);
+            }
+            "rewind" => {
+                let addr = self.parse_address(args.next())?;
+                if !self.machine.run_back_to(addr) {
+                    say!(out, "Never reached {:#06x}", addr);
+                }
+                self.show_current(out);
+            }
+            "c" | "continue" => {
+                let limit = count(args.next(), DEFAULT_CYCLE_LIMIT)?;
+                self.continue_execution(limit, out);
+            }
+            "b" | "break" => {
+                let addr = self.parse_address(args.next())?;
+                self.machine.add_breakpoint(addr);
+                say!(out, "Breakpoint set at {:#06x}", addr);
+            }
+            "d" | "delete" => {
+                let addr = self.parse_address(args.next())?;
+                if !self.machine.remove_breakpoint(addr) {
+                    return Err(format!("No breakpoint at {:#06x}", addr));
+                }
+            }
+            "breakpoints" => {
+                for &addr in self.machine.breakpoints() {
+                    match self.label_at(addr) {
+                        Some(label) => say!(out, "{:#06x} ({})", addr, label),
+                        None => say!(out, "{:#06x}", addr),
+                    }
+                }
+            }
+            "w" | "watch" => {
+                let args: Vec<&str> = args.collect();
+                self.watch(&args, out)?;
+            }
+            "unwatch" => self.unwatch(args.next())?,
+            "watchpoints" => self.show_watchpoints(out),
+            "r" | "registers" => self.show_registers(out),
+            "set" => self.set(args.next(), args.next())?,
+            "m" | "memory" => {
+                let addr = self.parse_address(args.next())?;
+                let n = count(args.next(), 32)?;
+                self.show_memory(addr, n, out);
+            }
+            "x" | "disassemble" => {
+                let addr = match args.next() {
+                    Some(arg) => Some(self.parse_address(Some(arg))?),
+                    None => None,
+                };
+                let n = count(args.next(), 10)?;
+                self.show_disassembly(addr, n, out);
+            }
+            "i" | "interrupts" => self.show_interrupts(out),
+            "hw" | "hardware" => self.show_hardware(out),
+            "trace" => match args.next() {
+                Some("off") => {
+                    self.machine.clear_tracer();
+                }
+                Some(path) => {
+                    let file =
+                        fs::File::create(path).map_err(|err| format!("{}: {}", path, err))?;
+                    let writer = io::BufWriter::new(file);
+                    if args.next() == Some("binary") {
+                        self.machine.set_tracer(BinaryTrace::new(writer));
+                    } else {
+                        self.machine.set_tracer(TextTrace::new(writer));
+                    }
+                    say!(out, "Tracing to {}", path);
+                }
+                None => self.machine.set_tracer(TextTrace::new(io::stdout())),
+            },
+            "save" => {
+                let path = args.next().ok_or("Expected a file name")?;
+                let snapshot = self.machine.snapshot().map_err(|err| err.to_string())?;
+                snapshot
+                    .save_file(path)
+                    .map_err(|err| format!("{}: {}", path, err))?;
+                say!(out, "Saved snapshot to {}", path);
+            }
+            "load" => {
+                let path = args.next().ok_or("Expected a file name")?;
+                let snapshot =
+                    Snapshot::load_file(path).map_err(|err| format!("{}: {}", path, err))?;
+                self.machine
+                    .restore(&snapshot)
+                    .map_err(|err| format!("{}: {}", path, err))?;
+                self.show_current(out);
+            }
+            "h" | "help" => say!(out, "{}", HELP),
+            "q" | "quit" => return Ok(false),
+            _ => return Err(format!("Unknown command '{}', try 'help'", command)),
+        }
+
+        Ok(true)
+    }
+
+    /// Reads commands from `input` until it ends or `quit`, writing a prompt before each one and
+    /// whatever it prints to `output`. An empty line repeats the last command.
+    fn run<R: BufRead, W: Write>(&mut self, mut input: R, output: &mut W) -> io::Result<()> {
+        let mut out = String::new();
+        self.show_current(&mut out);
+        output.write_all(out.as_bytes())?;
+
+        let mut last_command = String::new();
+        loop {
+            write!(output, "(dcpu) ")?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                break;
+            }
+
+            let line = line.trim();
+            let command = if line.is_empty() {
+                last_command.clone()
+            } else {
+                line.to_owned()
+            };
+            last_command = command.clone();
+
+            let mut out = String::new();
+            let result = self.execute(&command, &mut out);
+            output.write_all(out.as_bytes())?;
+            match result {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => writeln!(output, "{}", err)?,
+            }
+        }
+        output.flush()
+    }
+}
+
+/// A processor with the standard hardware attached
+fn new_machine() -> Processor {
+    let mut machine = Processor::new();
+    machine.connect_hardware(Monitor::new());
+    machine.connect_hardware(Keyboard::new());
+    machine.connect_hardware(Clock::new());
+    machine
+}
+
+fn main() {
+    let mut endianness = Endianness::Big;
+    let mut base = 0x0000;
+    let mut path = None;
+    let mut gdb_port = None;
+    let mut gdb_stdio = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--little-endian" => endianness = Endianness::Little,
+            "--gdb" => {
+                gdb_port = match args.next().and_then(|port| port.parse::<u16>().ok()) {
+                    Some(port) => Some(port),
+                    None => {
+                        eprintln!("{}", USAGE);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--gdb-stdio" => gdb_stdio = true,
+            "--dap" => {
+                // Programs are chosen by the editor's launch request
+                let mut server = DapServer::new(io::stdin(), io::stdout(), new_machine);
+                if let Err(err) = server.run() {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                }
+                return;
+            }
+            "--base" => {
+                base = match args.next().as_deref().and_then(parse_literal) {
+                    Some(base) => base,
+                    None => {
+                        eprintln!("{}", USAGE);
+                        process::exit(1);
+                    }
+                }
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(1);
+            }
+        }
+    }
+
+    let path = match path {
+        Some(path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    let mut debugger = match Debugger::load(new_machine(), &path, base, endianness) {
+        Ok(debugger) => debugger,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    if gdb_stdio {
+        let result = GdbStub::new(&mut debugger.machine, StdioConnection).run();
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(port) = gdb_port {
+        eprintln!("Waiting for GDB on 127.0.0.1:{}", port);
+        if let Err(err) = serve_gdb(&mut debugger.machine, ("127.0.0.1", port)) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let result = debugger.run(stdin.lock(), &mut stdout.lock());
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,130 @@
+use super::hardware::HardwareDevice;
+use super::processor::Processor;
+use super::processor::Register::*;
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
+
+/// The DCPU-16 runs at 100kHz
+pub const PROCESSOR_FREQUENCY: usize = 100_000;
+
+/// Generic Clock, ticking 60/B times per second of emulated time
+pub struct Clock {
+    pub interval: u16,
+    pub interrupt_message: u16,
+    start_cycle: usize,
+    ticks: usize,
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new()
+    }
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock {
+            interval: 0,
+            interrupt_message: 0,
+            start_cycle: 0,
+            ticks: 0,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.interval > 0
+    }
+
+    /// Number of ticks since the clock was last started, as of the last update
+    pub fn ticks(&self) -> u16 {
+        self.ticks as u16
+    }
+
+    /// Message to trigger on every tick, if interrupts are turned on
+    pub fn interrupt_message(&self) -> Option<u16> {
+        if self.interrupt_message > 0 {
+            Some(self.interrupt_message)
+        } else {
+            None
+        }
+    }
+
+    /// (Re)starts the clock at `cycle`, ticking 60/`interval` times per second. An interval of 0
+    /// turns the clock off.
+    pub fn start(&mut self, cycle: usize, interval: u16) {
+        self.interval = interval;
+        self.start_cycle = cycle;
+        self.ticks = 0;
+    }
+
+    /// Catches the clock up to the processor's `cycle`, returning how many times it ticked
+    pub fn update(&mut self, cycle: usize) -> usize {
+        if !self.is_running() {
+            return 0;
+        }
+
+        let elapsed = cycle.wrapping_sub(self.start_cycle) as u64;
+        let cycles_per_tick = PROCESSOR_FREQUENCY as u64 * self.interval as u64;
+        let ticks = (elapsed * 60 / cycles_per_tick) as usize;
+        let new_ticks = ticks.saturating_sub(self.ticks);
+        self.ticks = ticks;
+
+        new_ticks
+    }
+}
+
+impl HardwareDevice for Clock {
+    fn id(&self) -> u32 {
+        0x12D0B402
+    }
+    fn version(&self) -> u16 {
+        0x0001
+    }
+    fn manufacturer(&self) -> u32 {
+        0x00000000
+    }
+    fn handle_interrupt(&mut self, processor: &mut Processor) {
+        let op = processor.get_register(A);
+        let param = processor.get_register(B);
+        let cycle = processor.cycle();
+        self.update(cycle);
+
+        match op {
+            0x00 => self.start(cycle, param),
+            0x01 => processor.set_register(C, self.ticks()),
+            0x02 => self.interrupt_message = param,
+            _ => {}
+        }
+    }
+    fn tick(&mut self, processor: &mut Processor) {
+        let ticks = self.update(processor.cycle());
+        if let Some(message) = self.interrupt_message() {
+            for _ in 0..ticks {
+                processor.trigger_interrupt(message);
+            }
+        }
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_u16(self.interval);
+        writer.write_u16(self.interrupt_message);
+        writer.write_u64(self.start_cycle as u64);
+        writer.write_u64(self.ticks as u64);
+        writer.into_bytes()
+    }
+    fn load_state(&mut self, state: &[u8], version: u16) -> Result<(), SnapshotError> {
+        if version != 1 {
+            return Err(SnapshotError::InvalidDeviceState(self.id()));
+        }
+        let mut reader = StateReader::new(state);
+        let interval = reader.read_u16()?;
+        let interrupt_message = reader.read_u16()?;
+        let start_cycle = reader.read_u64()? as usize;
+        let ticks = reader.read_u64()? as usize;
+
+        self.interval = interval;
+        self.interrupt_message = interrupt_message;
+        self.start_cycle = start_cycle;
+        self.ticks = ticks;
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+//! Cycle costs from the DCPU-16 spec, in `docs/DCPU-16Spec.txt`
+
+use super::opcodes::OpCode;
+use super::value::Value;
+
+/// Cycles taken by each basic opcode, indexed by opcode. Unused opcodes are 0.
+pub const BASIC_CYCLES: [u16; 32] = [
+    0, // Special
+    1, // SET
+    2, // ADD
+    2, // SUB
+    2, // MUL
+    2, // MLI
+    3, // DIV
+    3, // DVI
+    3, // MOD
+    3, // MDI
+    1, // AND
+    1, // BOR
+    1, // XOR
+    1, // SHR
+    1, // ASR
+    1, // SHL
+    2, // IFB
+    2, // IFC
+    2, // IFE
+    2, // IFN
+    2, // IFG
+    2, // IFA
+    2, // IFL
+    2, // IFU
+    0, 0, //
+    3, // ADX
+    3, // SBX
+    0, 0, //
+    2, // STI
+    2, // STD
+];
+
+/// Cycles taken by each special opcode, indexed by opcode. Unused opcodes are 0.
+pub const SPECIAL_CYCLES: [u16; 32] = [
+    0, //
+    3, // JSR
+    0, 0, 0, 0, 0, 0, //
+    4, // INT
+    1, // IAG
+    1, // IAS
+    3, // RFI
+    2, // IAQ
+    0, 0, 0, //
+    2, // HWN
+    4, // HWQ
+    4, // HWI, plus however long the hardware takes
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, //
+];
+
+/// Extra cycles for looking up a value that uses the next word
+pub const NEXT_WORD_CYCLES: u16 = 1;
+
+/// Extra cycles when a conditional's test fails, and again for each chained conditional skipped
+/// along with the next instruction
+pub const SKIP_CYCLES: u16 = 1;
+
+pub fn basic_cycles(op: OpCode) -> u16 {
+    BASIC_CYCLES.get(op as usize).cloned().unwrap_or(0)
+}
+
+pub fn special_cycles(op: OpCode) -> u16 {
+    SPECIAL_CYCLES.get(op as usize).cloned().unwrap_or(0)
+}
+
+/// Extra cycles needed to look up `value`
+pub fn value_cycles(value: Value) -> u16 {
+    if value.uses_next_word() {
+        NEXT_WORD_CYCLES
+    } else {
+        0
+    }
+}
@@ -0,0 +1,642 @@
+use super::assembler::{parse_literal, Assembler};
+use super::journal::DEFAULT_JOURNAL_CAPACITY;
+use super::json::Json;
+use super::memory::Endianness;
+use super::processor::{Processor, Register};
+use super::watch::StopReason;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Cycles to run between checks for new requests while the program is running
+pub(crate) const RUN_CHUNK: usize = 10_000;
+
+/// Cycles `stepOut` will run before giving up
+const STEP_OUT_LIMIT: usize = 10_000_000;
+
+/// The DCPU only has one thread of execution
+const THREAD_ID: u64 = 1;
+
+const REGISTERS_REF: u64 = 1;
+const STACK_REF: u64 = 2;
+const MEMORY_REF: u64 = 3;
+const HARDWARE_REF: u64 = 4;
+/// Memory is split into 256 pages of 256 words, referenced from `MEMORY_PAGE_REF`
+const MEMORY_PAGE_REF: u64 = 0x100;
+
+/// How many words of the stack the Stack scope shows
+const STACK_DEPTH: usize = 64;
+
+/// Reads one `Content-Length` framed message
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() && length.is_some() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or(0)];
+    reader.read_exact(&mut body)?;
+    match Json::parse(&String::from_utf8_lossy(&body)) {
+        Some(message) => Ok(Some(message)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid JSON message",
+        )),
+    }
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn variable(name: String, value: String, reference: u64) -> Json {
+    Json::object(vec![
+        ("name", name.into()),
+        ("value", value.into()),
+        ("variablesReference", reference.into()),
+    ])
+}
+
+/// The program being debugged
+struct Session {
+    machine: Processor,
+    /// The `.dasm` file the program was assembled from, along with its line map and labels
+    source: Option<(PathBuf, Assembler)>,
+    source_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+}
+
+impl Session {
+    fn sync_breakpoints(&mut self) {
+        let existing: Vec<u16> = self.machine.breakpoints().iter().cloned().collect();
+        for addr in existing {
+            self.machine.remove_breakpoint(addr);
+        }
+        for &addr in self
+            .source_breakpoints
+            .iter()
+            .chain(&self.instruction_breakpoints)
+        {
+            self.machine.add_breakpoint(addr);
+        }
+    }
+
+    /// Names `addr` after the closest label before it
+    fn describe(&self, addr: u16) -> String {
+        let label = self.source.as_ref().and_then(|(_, assembler)| {
+            assembler
+                .labels()
+                .iter()
+                .filter(|&(_, &label_addr)| label_addr <= addr)
+                .max_by_key(|&(_, &label_addr)| label_addr)
+        });
+        match label {
+            Some((name, &label_addr)) if label_addr == addr => name.clone(),
+            Some((name, &label_addr)) => format!("{}+{}", name, addr - label_addr),
+            None => format!("{:#06x}", addr),
+        }
+    }
+}
+
+/// A Debug Adapter Protocol server, for debugging DCPU programs from editors.
+///
+/// Requests are read on a background thread so a running program can be paused. Programs are
+/// launched with `{"program": path, "stopOnEntry": bool, "base": addr, "littleEndian": bool}`,
+/// where `.dasm` files are assembled and anything else is loaded as a binary image.
+pub struct DapServer<W: Write> {
+    requests: Receiver<Json>,
+    output: W,
+    make_processor: Box<dyn FnMut() -> Processor>,
+    session: Option<Session>,
+    seq: u64,
+    is_running: bool,
+    stop_on_entry: bool,
+    /// Events to send once the current request has been responded to
+    events: Vec<Json>,
+}
+
+impl<W: Write> DapServer<W> {
+    /// Serves requests from `input`, creating the machine for each launch with `make_processor`
+    pub fn new<R, F>(input: R, output: W, make_processor: F) -> DapServer<W>
+    where
+        R: 'static + Read + Send,
+        F: 'static + FnMut() -> Processor,
+    {
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(input);
+            while let Ok(Some(message)) = read_message(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        DapServer {
+            requests,
+            output,
+            make_processor: Box::new(make_processor),
+            session: None,
+            seq: 0,
+            is_running: false,
+            stop_on_entry: false,
+            events: vec![],
+        }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    /// Serves requests until the client disconnects
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            let request = if self.is_running {
+                match self.requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match self.requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            match request {
+                Some(request) => {
+                    if !self.handle(&request)? {
+                        return Ok(());
+                    }
+                }
+                None => self.run_chunk()?,
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        message.insert(0, ("seq", self.seq.into()));
+        write_message(&mut self.output, &Json::object(message))
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.events.push(Json::object(vec![
+            ("type", "event".into()),
+            ("event", event.into()),
+            ("body", body),
+        ]));
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) {
+        self.is_running = false;
+        let mut body = vec![
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ];
+        if let Some(description) = description {
+            body.push(("description", description.clone().into()));
+            body.push(("text", description.into()));
+        }
+        self.event("stopped", Json::object(body));
+    }
+
+    fn flush_events(&mut self) -> io::Result<()> {
+        for event in std::mem::take(&mut self.events) {
+            let fields = match event {
+                Json::Object(fields) => fields,
+                _ => continue,
+            };
+            self.seq += 1;
+            let mut message = vec![("seq".to_owned(), self.seq.into())];
+            message.extend(fields);
+            write_message(&mut self.output, &Json::Object(message))?;
+        }
+        Ok(())
+    }
+
+    /// Handles one request, returning false when the client has disconnected
+    fn handle(&mut self, request: &Json) -> io::Result<bool> {
+        let command = request.get("command").as_str().unwrap_or("").to_owned();
+        let args = request.get("arguments");
+        let result = match command.as_str() {
+            "initialize" => Ok(Json::object(vec![
+                ("supportsConfigurationDoneRequest", true.into()),
+                ("supportsInstructionBreakpoints", true.into()),
+                ("supportsStepBack", true.into()),
+                ("supportsTerminateRequest", true.into()),
+            ])),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(Json::object(vec![])),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stopped("entry", None);
+                } else {
+                    self.is_running = true;
+                }
+                Ok(Json::Null)
+            }
+            "threads" => Ok(Json::object(vec![(
+                "threads",
+                vec![Json::object(vec![
+                    ("id", THREAD_ID.into()),
+                    ("name", "DCPU-16".into()),
+                ])]
+                .into(),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(Self::scopes()),
+            "variables" => self.variables(args),
+            "continue" => {
+                self.is_running = true;
+                Ok(Json::object(vec![("allThreadsContinued", true.into())]))
+            }
+            "next" | "stepIn" => self.step(),
+            "stepOut" => self.step_out(),
+            "stepBack" => self.step_back(),
+            "reverseContinue" => self.reverse_continue(),
+            "pause" => {
+                self.stopped("pause", None);
+                Ok(Json::Null)
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, &command, Ok(Json::Null))?;
+                return Ok(false);
+            }
+            _ => Err(format!("Unsupported request '{}'", command)),
+        };
+
+        self.respond(request, &command, result)?;
+        self.flush_events()?;
+        Ok(true)
+    }
+
+    fn respond(
+        &mut self,
+        request: &Json,
+        command: &str,
+        result: Result<Json, String>,
+    ) -> io::Result<()> {
+        let request_seq = request.get("seq").as_u64().unwrap_or(0);
+        let mut message = vec![
+            ("type", "response".into()),
+            ("request_seq", request_seq.into()),
+            ("command", command.into()),
+        ];
+        match result {
+            Ok(body) => {
+                message.push(("success", true.into()));
+                message.push(("body", body));
+            }
+            Err(err) => {
+                message.push(("success", false.into()));
+                message.push(("message", err.into()));
+            }
+        }
+        self.send(message)
+    }
+
+    fn session(&mut self) -> Result<&mut Session, String> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| "No program has been launched".to_owned())
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        let path = PathBuf::from(args.get("program").as_str().ok_or("Missing 'program'")?);
+        let base = args.get("base").as_u64().unwrap_or(0) as u16;
+        let endianness = if args.get("littleEndian").as_bool() == Some(true) {
+            Endianness::Little
+        } else {
+            Endianness::Big
+        };
+
+        let mut machine = (self.make_processor)();
+        let assembler = machine
+            .memory_mut()
+            .load_program_file(base, &path, endianness)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        let source = assembler.map(|assembler| (path, assembler));
+        machine.set_register(Register::PC, base);
+        machine.enable_journal(DEFAULT_JOURNAL_CAPACITY);
+
+        self.session = Some(Session {
+            machine,
+            source,
+            source_breakpoints: vec![],
+            instruction_breakpoints: vec![],
+        });
+        self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+        self.event("initialized", Json::Null);
+        Ok(Json::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let session = self.session()?;
+        let path = args.get("source").get("path").as_str().map(PathBuf::from);
+        let assembler = match (&session.source, &path) {
+            (Some((source, assembler)), Some(path)) if is_same_file(source, path) => {
+                Some(assembler)
+            }
+            _ => None,
+        };
+
+        let mut addrs = vec![];
+        let mut breakpoints = vec![];
+        for breakpoint in args.get("breakpoints").as_array() {
+            let line = breakpoint.get("line").as_u64().unwrap_or(0) as usize;
+            let found = assembler.and_then(|assembler| assembler.addr_of_line(line));
+            breakpoints.push(match found {
+                Some((addr, line)) => {
+                    addrs.push(addr);
+                    Json::object(vec![
+                        ("verified", true.into()),
+                        ("line", line.into()),
+                        ("instructionReference", format!("{:#06x}", addr).into()),
+                    ])
+                }
+                None => Json::object(vec![
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", "No code at this line".into()),
+                ]),
+            });
+        }
+
+        session.source_breakpoints = addrs;
+        session.sync_breakpoints();
+        Ok(Json::object(vec![("breakpoints", breakpoints.into())]))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let session = self.session()?;
+        let mut addrs = vec![];
+        let mut breakpoints = vec![];
+        for breakpoint in args.get("breakpoints").as_array() {
+            let reference = breakpoint.get("instructionReference").as_str();
+            let offset = match breakpoint.get("offset") {
+                Json::Number(offset) => *offset as i64,
+                _ => 0,
+            };
+            match reference.and_then(parse_literal) {
+                Some(addr) => {
+                    let addr = (addr as i64 + offset) as u16;
+                    addrs.push(addr);
+                    breakpoints.push(Json::object(vec![
+                        ("verified", true.into()),
+                        ("instructionReference", format!("{:#06x}", addr).into()),
+                    ]));
+                }
+                None => breakpoints.push(Json::object(vec![
+                    ("verified", false.into()),
+                    ("message", "Invalid address".into()),
+                ])),
+            }
+        }
+
+        session.instruction_breakpoints = addrs;
+        session.sync_breakpoints();
+        Ok(Json::object(vec![("breakpoints", breakpoints.into())]))
+    }
+
+    fn stack_trace(&mut self) -> Result<Json, String> {
+        let session = self.session()?;
+        let pc = session.machine.get_register(Register::PC);
+        let text = session
+            .machine
+            .disassemble(pc, 1)
+            .first()
+            .map(|line| line.text.clone())
+            .unwrap_or_default();
+
+        let mut frame = vec![
+            ("id", 0u64.into()),
+            ("name", format!("{}: {}", session.describe(pc), text).into()),
+            ("instructionPointerReference", format!("{:#06x}", pc).into()),
+        ];
+        let line = session
+            .source
+            .as_ref()
+            .and_then(|(path, assembler)| Some((path, assembler.line_at(pc)?)));
+        match line {
+            Some((path, line)) => {
+                let name = path.file_name().map(|name| name.to_string_lossy());
+                frame.push((
+                    "source",
+                    Json::object(vec![
+                        ("name", name.unwrap_or_default().into_owned().into()),
+                        ("path", path.to_string_lossy().into_owned().into()),
+                    ]),
+                ));
+                frame.push(("line", line.into()));
+                frame.push(("column", 1u64.into()));
+            }
+            None => {
+                frame.push(("line", 0u64.into()));
+                frame.push(("column", 0u64.into()));
+            }
+        }
+
+        Ok(Json::object(vec![
+            ("stackFrames", vec![Json::object(frame)].into()),
+            ("totalFrames", 1u64.into()),
+        ]))
+    }
+
+    fn scopes() -> Json {
+        let scope = |name: &str, reference: u64, expensive: bool| {
+            Json::object(vec![
+                ("name", name.into()),
+                ("variablesReference", reference.into()),
+                ("expensive", expensive.into()),
+            ])
+        };
+        Json::object(vec![(
+            "scopes",
+            vec![
+                scope("Registers", REGISTERS_REF, false),
+                scope("Stack", STACK_REF, false),
+                scope("Memory", MEMORY_REF, true),
+                scope("Hardware", HARDWARE_REF, false),
+            ]
+            .into(),
+        )])
+    }
+
+    fn variables(&mut self, args: &Json) -> Result<Json, String> {
+        let session = self.session()?;
+        let machine = &session.machine;
+        let reference = args.get("variablesReference").as_u64().unwrap_or(0);
+        let variables = match reference {
+            REGISTERS_REF => Register::ALL
+                .iter()
+                .map(|&register| {
+                    let value = machine.get_register(register);
+                    variable(register.to_string(), format!("{:#06x}", value), 0)
+                })
+                .collect(),
+            STACK_REF => {
+                let sp = machine.get_register(Register::SP);
+                let depth = (0x10000 - sp as usize) % 0x10000;
+                (0..depth.min(STACK_DEPTH))
+                    .map(|i| {
+                        let addr = sp.wrapping_add(i as u16);
+                        let value = machine.get_memory(addr);
+                        variable(
+                            format!("[SP+{}]", i),
+                            format!("{:#06x} @ {:#06x}", value, addr),
+                            0,
+                        )
+                    })
+                    .collect()
+            }
+            MEMORY_REF => (0..0x100)
+                .map(|page| {
+                    let start = page * 0x100;
+                    variable(
+                        format!("{:#06x}", start),
+                        format!("{:#06x}..{:#06x}", start, start + 0xff),
+                        MEMORY_PAGE_REF + page as u64,
+                    )
+                })
+                .collect(),
+            HARDWARE_REF => (0..machine.hardware_count())
+                .filter_map(|index| {
+                    let rc = machine.get_hardware(index)?;
+                    let hardware = rc.try_borrow().ok()?;
+                    Some(variable(
+                        format!("{}", index),
+                        format!(
+                            "id {:#010x}, version {:#06x}, manufacturer {:#010x}",
+                            hardware.id(),
+                            hardware.version(),
+                            hardware.manufacturer()
+                        ),
+                        0,
+                    ))
+                })
+                .collect(),
+            _ if (MEMORY_PAGE_REF..MEMORY_PAGE_REF + 0x100).contains(&reference) => {
+                let page = ((reference - MEMORY_PAGE_REF) * 0x100) as u16;
+                (0..16)
+                    .map(|row| {
+                        let start = page + row * 16;
+                        let words: Vec<String> = (0..16)
+                            .map(|i| format!("{:04x}", machine.get_memory(start + i)))
+                            .collect();
+                        variable(format!("{:#06x}", start), words.join(" "), 0)
+                    })
+                    .collect()
+            }
+            _ => return Err(format!("Unknown variables reference {}", reference)),
+        };
+
+        Ok(Json::object(vec![("variables", Json::Array(variables))]))
+    }
+
+    fn step(&mut self) -> Result<Json, String> {
+        let result = self.session()?.machine.step_instruction();
+        match result {
+            Ok(()) => self.stopped("step", None),
+            Err(err) => self.stopped("exception", Some(err.to_string())),
+        }
+        Ok(Json::Null)
+    }
+
+    /// Runs until the stack is shallower than it is now, i.e. the current subroutine returns
+    fn step_out(&mut self) -> Result<Json, String> {
+        let session = self.session()?;
+        let machine = &mut session.machine;
+        let depth =
+            |machine: &Processor| (0x10000 - machine.get_register(Register::SP) as usize) % 0x10000;
+        let start_depth = depth(machine);
+        let start_cycle = machine.cycle();
+
+        let mut reason = ("step", None);
+        while machine.cycle().wrapping_sub(start_cycle) < STEP_OUT_LIMIT {
+            if let Err(err) = machine.step_instruction() {
+                reason = ("exception", Some(err.to_string()));
+                break;
+            }
+            if depth(machine) < start_depth {
+                break;
+            }
+            let pc = machine.get_register(Register::PC);
+            if machine.breakpoints().contains(&pc) {
+                reason = ("breakpoint", None);
+                break;
+            }
+        }
+
+        self.stopped(reason.0, reason.1);
+        Ok(Json::Null)
+    }
+
+    fn step_back(&mut self) -> Result<Json, String> {
+        if !self.session()?.machine.step_back() {
+            return Err("No more history".to_owned());
+        }
+        self.stopped("step", None);
+        Ok(Json::Null)
+    }
+
+    fn reverse_continue(&mut self) -> Result<Json, String> {
+        let machine = &mut self.session()?.machine;
+        let mut reason = "entry";
+        while machine.step_back() {
+            let pc = machine.get_register(Register::PC);
+            if machine.breakpoints().contains(&pc) {
+                reason = "breakpoint";
+                break;
+            }
+        }
+
+        self.stopped(reason, None);
+        Ok(Json::Null)
+    }
+
+    fn run_chunk(&mut self) -> io::Result<()> {
+        let reason = match self.session.as_mut() {
+            Some(session) => session.machine.run_until(RUN_CHUNK),
+            None => {
+                self.is_running = false;
+                return Ok(());
+            }
+        };
+
+        match reason {
+            StopReason::CycleLimit => return Ok(()),
+            StopReason::Breakpoint(_) => self.stopped("breakpoint", None),
+            StopReason::MemoryRead { .. }
+            | StopReason::MemoryWrite { .. }
+            | StopReason::RegisterChanged { .. } => self.stopped("data breakpoint", None),
+            StopReason::Error(err) => self.stopped("exception", Some(err.to_string())),
+        }
+        self.flush_events()
+    }
+}
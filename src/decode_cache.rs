@@ -0,0 +1,47 @@
+use super::instruction::Instruction;
+use super::memory::Memory;
+
+/// Instructions that have already been decoded, keyed by the address of their first word.
+/// Entries must be invalidated whenever the word at their address changes, so that
+/// self-modifying code sees its own writes.
+#[derive(Clone, Debug, Default)]
+pub struct DecodeCache {
+    /// Allocated on first use, so processors that never run don't pay for it
+    entries: Vec<Option<Instruction>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache { entries: vec![] }
+    }
+
+    /// The instruction at `addr`, decoding it from `memory` if it isn't cached yet
+    #[inline]
+    pub fn get(&mut self, memory: &Memory, addr: u16) -> Instruction {
+        if self.entries.is_empty() {
+            self.entries = vec![None; 0x10000];
+        }
+
+        let entry = &mut self.entries[addr as usize];
+        match entry {
+            Some(instruction) => *instruction,
+            None => {
+                let instruction = memory.get_instruction(addr);
+                *entry = Some(instruction);
+                instruction
+            }
+        }
+    }
+
+    /// Forgets the instruction starting at `addr`
+    pub fn invalidate(&mut self, addr: u16) {
+        if let Some(entry) = self.entries.get_mut(addr as usize) {
+            *entry = None;
+        }
+    }
+
+    /// Forgets every instruction, for when memory has changed wholesale
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,137 @@
+use super::instruction::Instruction;
+use super::opcodes::{self, SPL};
+use super::value::Value;
+use std::fmt;
+
+/// A single disassembled instruction and the words it was decoded from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Disassembly {
+    pub addr: u16,
+    pub words: Vec<u16>,
+    pub text: String,
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words: Vec<String> = self.words.iter().map(|w| format!("{:04x}", w)).collect();
+        write!(
+            f,
+            "{:04x}: {:<14} {}",
+            self.addr,
+            words.join(" "),
+            self.text
+        )
+    }
+}
+
+fn format_number(value: u16) -> String {
+    if value < 10 {
+        format!("{}", value)
+    } else {
+        format!("{:#x}", value)
+    }
+}
+
+/// Consumes the extra word an operand needs, if any
+fn read_next_word<F: Fn(u16) -> Option<u16>>(
+    read: &F,
+    addr: u16,
+    words: &mut Vec<u16>,
+) -> Option<u16> {
+    let word = read(addr.wrapping_add(words.len() as u16))?;
+    words.push(word);
+    Some(word)
+}
+
+fn format_operand<F: Fn(u16) -> Option<u16>>(
+    read: &F,
+    addr: u16,
+    words: &mut Vec<u16>,
+    value: Value,
+    is_a: bool,
+) -> Option<String> {
+    let text = match value {
+        Value::Register(reg) => format!("{}", reg),
+        Value::RegisterPointer(reg) => format!("[{}]", reg),
+        Value::RegisterPointerOffset(reg) => {
+            let offset = read_next_word(read, addr, words)?;
+            format!("[{}+{}]", reg, format_number(offset))
+        }
+        Value::Push | Value::Pop if is_a => "POP".to_owned(),
+        Value::Push | Value::Pop => "PUSH".to_owned(),
+        Value::Peek => "PEEK".to_owned(),
+        Value::Pick => {
+            let offset = read_next_word(read, addr, words)?;
+            format!("PICK {}", format_number(offset))
+        }
+        Value::NextWordPointer => {
+            let pointer = read_next_word(read, addr, words)?;
+            format!("[{}]", format_number(pointer))
+        }
+        Value::NextWord => format_number(read_next_word(read, addr, words)?),
+        Value::Literal(literal) => format_number(literal),
+        Value::OpCode(op) => format_number(op),
+    };
+
+    Some(text)
+}
+
+/// Decodes a single instruction at `addr`, returning `None` if it's invalid or truncated
+fn decode<F: Fn(u16) -> Option<u16>>(read: &F, addr: u16) -> Option<Disassembly> {
+    let word = read(addr)?;
+    let instruction = Instruction::from(word);
+    let mut words = vec![word];
+
+    let text = if instruction.op() == SPL {
+        let op = match instruction.b() {
+            Value::OpCode(op) => op,
+            _ => return None,
+        };
+        let mnemonic = opcodes::special_mnemonic(op)?;
+        let a = format_operand(read, addr, &mut words, instruction.a(), true)?;
+        format!("{} {}", mnemonic, a)
+    } else {
+        let mnemonic = opcodes::basic_mnemonic(instruction.op())?;
+        // The extra word for `a` comes before `b`'s
+        let a = format_operand(read, addr, &mut words, instruction.a(), true)?;
+        let b = format_operand(read, addr, &mut words, instruction.b(), false)?;
+        format!("{} {}, {}", mnemonic, b, a)
+    };
+
+    Some(Disassembly { addr, words, text })
+}
+
+/// Disassembles the instruction at `addr`, falling back to a `DAT` of the first word when it
+/// isn't a valid instruction
+pub fn disassemble_at<F: Fn(u16) -> Option<u16>>(read: F, addr: u16) -> Option<Disassembly> {
+    if let Some(disassembly) = decode(&read, addr) {
+        return Some(disassembly);
+    }
+
+    let word = read(addr)?;
+    Some(Disassembly {
+        addr,
+        words: vec![word],
+        text: format!("DAT {:#06x}", word),
+    })
+}
+
+/// Disassembles every instruction in `words`, which are loaded at `base`
+pub fn disassemble(words: &[u16], base: u16) -> Vec<Disassembly> {
+    let read = |addr: u16| words.get(addr.wrapping_sub(base) as usize).cloned();
+    let mut output = vec![];
+    let mut offset = 0;
+
+    while offset < words.len() {
+        let addr = base.wrapping_add(offset as u16);
+        match disassemble_at(read, addr) {
+            Some(disassembly) => {
+                offset += disassembly.words.len();
+                output.push(disassembly);
+            }
+            None => break,
+        }
+    }
+
+    output
+}
@@ -0,0 +1,33 @@
+use super::opcodes::OpCode;
+use std::error::Error;
+use std::fmt;
+
+/// Errors raised while decoding or executing guest code
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ProcessorError {
+    InvalidOpCode(OpCode),
+    InvalidSpecialOpCode(OpCode),
+    InvalidValue(u16),
+    InvalidRegister(u16),
+    /// The hardware device at this index is already in use
+    DeviceBusy(u16),
+    /// The interrupt queue overflowed and the processor has caught fire
+    OnFire,
+}
+
+impl fmt::Display for ProcessorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessorError::InvalidOpCode(op) => write!(f, "invalid op code {:#04x}", op),
+            ProcessorError::InvalidSpecialOpCode(op) => {
+                write!(f, "invalid special op code {:#04x}", op)
+            }
+            ProcessorError::InvalidValue(value) => write!(f, "invalid value code {:#04x}", value),
+            ProcessorError::InvalidRegister(value) => write!(f, "invalid register {}", value),
+            ProcessorError::DeviceBusy(index) => write!(f, "hardware device {} is busy", index),
+            ProcessorError::OnFire => write!(f, "processor is on fire"),
+        }
+    }
+}
+
+impl Error for ProcessorError {}
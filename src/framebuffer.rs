@@ -0,0 +1,80 @@
+/// Width of the LEM1802's display area in pixels, not counting the border
+pub const SCREEN_WIDTH: usize = 128;
+/// Height of the LEM1802's display area in pixels, not counting the border
+pub const SCREEN_HEIGHT: usize = 96;
+pub const CHAR_WIDTH: usize = 4;
+pub const CHAR_HEIGHT: usize = 8;
+
+/// Converts a 12 bit `0000rrrrggggbbbb` palette color to 8 bit channels
+pub fn color_to_rgb(color: u16) -> [u8; 3] {
+    let r = ((color & 0b0000111100000000) >> 8) as u8;
+    let g = ((color & 0b0000000011110000) >> 4) as u8;
+    let b = (color & 0b0000000000001111) as u8;
+
+    [r * 17, g * 17, b * 17]
+}
+
+/// An RGB image with 3 bytes per pixel, row by row from the top left
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    /// A black image
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    /// An image from RGB pixels, or None if there are the wrong number of them
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<u8>) -> Option<Framebuffer> {
+        if pixels.len() == width * height * 3 {
+            Some(Framebuffer {
+                width,
+                height,
+                pixels,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = (x + y * self.width) * 3;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let i = (x + y * self.width) * 3;
+        self.pixels[i..i + 3].copy_from_slice(&rgb);
+    }
+
+    /// Fills a rectangle, clipped to the edges of the image
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, rgb: [u8; 3]) {
+        let right = (x + width).min(self.width);
+        let bottom = (y + height).min(self.height);
+        for y in y..bottom {
+            for x in x..right {
+                self.set_pixel(x, y, rgb);
+            }
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use super::processor::{Processor, Register};
+use super::watch::{StopReason, WatchKind};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// Registers are numbered in `Register::ALL` order
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.dcpu16.core">
+    <reg name="a" bitsize="16" regnum="0" type="uint16"/>
+    <reg name="b" bitsize="16" type="uint16"/>
+    <reg name="c" bitsize="16" type="uint16"/>
+    <reg name="x" bitsize="16" type="uint16"/>
+    <reg name="y" bitsize="16" type="uint16"/>
+    <reg name="z" bitsize="16" type="uint16"/>
+    <reg name="i" bitsize="16" type="uint16"/>
+    <reg name="j" bitsize="16" type="uint16"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="ex" bitsize="16" type="uint16"/>
+    <reg name="ia" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// Cycles to run between checks for an interrupt from the debugger while continuing
+pub(crate) const CONTINUE_CHUNK: usize = 10_000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// A byte stream to a debugger front-end
+pub trait Connection: Read + Write {
+    /// Reads whatever has already arrived without blocking, so a running program can be
+    /// interrupted. Connections that can't do this return 0 and can't be interrupted.
+    fn read_nonblocking(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Connection for TcpStream {
+    fn read_nonblocking(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.set_nonblocking(true)?;
+        let result = self.read(buf);
+        self.set_nonblocking(false)?;
+        match result {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            result => result,
+        }
+    }
+}
+
+/// Talks to the debugger over stdin and stdout, e.g. `target remote | dcpu --gdb-stdio prog.bin`
+#[derive(Default)]
+pub struct StdioConnection;
+
+impl Read for StdioConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::stdin().read(buf)
+    }
+}
+
+impl Write for StdioConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stdout().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+impl Connection for StdioConnection {}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+fn encode_words(words: &[u16]) -> String {
+    words
+        .iter()
+        .map(|word| format!("{:02x}{:02x}", word & 0xff, word >> 8))
+        .collect()
+}
+
+fn decode_words(hex: &str) -> Option<Vec<u16>> {
+    if !hex.len().is_multiple_of(4) {
+        return None;
+    }
+    (0..hex.len() / 4)
+        .map(|i| {
+            let low = u16::from_str_radix(hex.get(i * 4..i * 4 + 2)?, 16).ok()?;
+            let high = u16::from_str_radix(hex.get(i * 4 + 2..i * 4 + 4)?, 16).ok()?;
+            Some(low | high << 8)
+        })
+        .collect()
+}
+
+/// Splits `addr,len` into word addresses and lengths
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+    let mut parts = text.splitn(2, ',');
+    let addr = parse_hex(parts.next()?)?;
+    let len = parse_hex(parts.next()?)?;
+    if addr > 0xffff || len > 0x10000 {
+        return None;
+    }
+    Some((addr as u16, len as usize))
+}
+
+/// A GDB remote serial protocol server for a `Processor`.
+///
+/// Memory is word-addressed: addresses and lengths are in 16-bit words, and words and registers
+/// are sent little-endian.
+pub struct GdbStub<'a, C: Connection> {
+    processor: &'a mut Processor,
+    connection: C,
+    input: VecDeque<u8>,
+    no_ack: bool,
+}
+
+impl<'a, C: Connection> GdbStub<'a, C> {
+    pub fn new(processor: &'a mut Processor, connection: C) -> GdbStub<'a, C> {
+        GdbStub {
+            processor,
+            connection,
+            input: VecDeque::new(),
+            no_ack: false,
+        }
+    }
+
+    /// Serves requests until the debugger detaches, kills the program or disconnects
+    pub fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let reply = match self.handle_packet(&packet) {
+                Some(reply) => reply,
+                None => {
+                    self.write_packet("OK")?;
+                    return Ok(());
+                }
+            };
+            self.write_packet(&reply)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.input.is_empty() {
+            let mut buf = [0; 1024];
+            let len = self.connection.read(&mut buf)?;
+            self.input.extend(&buf[..len]);
+        }
+
+        Ok(self.input.pop_front())
+    }
+
+    /// Reads the next packet's data, acknowledging it unless no-ack mode is on
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => break,
+                // Acks, and interrupts while the program is already stopped
+                Some(_) => continue,
+            }
+        }
+
+        let mut data = vec![];
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+        let mut sum = [0; 2];
+        for byte in sum.iter_mut() {
+            *byte = match self.read_byte()? {
+                Some(byte) => byte,
+                None => return Ok(None),
+            };
+        }
+
+        if !self.no_ack {
+            let expected = format!("{:02x}", checksum(&data));
+            let is_valid = expected.as_bytes().eq_ignore_ascii_case(&sum);
+            self.connection
+                .write_all(if is_valid { b"+" } else { b"-" })?;
+            if !is_valid {
+                return self.read_packet();
+            }
+        }
+
+        Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        self.connection.write_all(packet.as_bytes())?;
+        self.connection.flush()
+    }
+
+    /// Handles one request, returning the reply, or None when the session is over
+    fn handle_packet(&mut self, packet: &str) -> Option<String> {
+        let reply = match packet {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => {
+                let registers: Vec<u16> = Register::ALL
+                    .iter()
+                    .map(|&register| self.processor.get_register(register))
+                    .collect();
+                encode_words(&registers)
+            }
+            "s" | "vCont;s" | "vCont;s:1" => self.step(),
+            "c" | "vCont;c" | "vCont;c:1" => self.resume(),
+            "vCont?" => "vCont;c;s".to_owned(),
+            "D" | "k" => return None,
+            "qAttached" => "1".to_owned(),
+            "qfThreadInfo" => "m1".to_owned(),
+            "qsThreadInfo" => "l".to_owned(),
+            "qC" => "QC1".to_owned(),
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_owned()
+            }
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=1000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_owned()
+            }
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                self.read_target_xml(&packet["qXfer:features:read:target.xml:".len()..])
+            }
+            _ if packet.starts_with('H') => "OK".to_owned(),
+            _ => {
+                // Anything malformed, including an empty packet, is unsupported
+                let kind = match packet.chars().next() {
+                    Some(kind) => kind,
+                    None => return Some(String::new()),
+                };
+                let args = &packet[kind.len_utf8()..];
+                match kind {
+                    'G' => self.write_registers(args),
+                    'p' => self.read_register(args),
+                    'P' => self.write_register(args),
+                    'm' => self.read_memory(args),
+                    'M' => self.write_memory(args),
+                    'Z' => self.set_breakpoint(args, true),
+                    'z' => self.set_breakpoint(args, false),
+                    _ => String::new(),
+                }
+            }
+        };
+
+        Some(reply)
+    }
+
+    fn read_target_xml(&self, range: &str) -> String {
+        let (offset, len) = match parse_range(range) {
+            Some(range) => range,
+            None => return "E01".to_owned(),
+        };
+        let xml = TARGET_XML.as_bytes();
+        let start = (offset as usize).min(xml.len());
+        let end = (start + len).min(xml.len());
+        let chunk = String::from_utf8_lossy(&xml[start..end]);
+        if end == xml.len() {
+            format!("l{}", chunk)
+        } else {
+            format!("m{}", chunk)
+        }
+    }
+
+    fn write_registers(&mut self, data: &str) -> String {
+        match decode_words(data) {
+            Some(ref values) if values.len() == Register::ALL.len() => {
+                for (&register, &value) in Register::ALL.iter().zip(values) {
+                    self.processor.set_register(register, value);
+                }
+                "OK".to_owned()
+            }
+            _ => "E01".to_owned(),
+        }
+    }
+
+    fn read_register(&self, number: &str) -> String {
+        match parse_hex(number).and_then(|i| Register::ALL.get(i as usize)) {
+            Some(&register) => encode_words(&[self.processor.get_register(register)]),
+            None => "E01".to_owned(),
+        }
+    }
+
+    fn write_register(&mut self, assignment: &str) -> String {
+        let mut parts = assignment.splitn(2, '=');
+        let register = parts
+            .next()
+            .and_then(parse_hex)
+            .and_then(|i| Register::ALL.get(i as usize));
+        let value = parts.next().and_then(decode_words);
+        match (register, value.as_deref()) {
+            (Some(&register), Some(&[value])) => {
+                self.processor.set_register(register, value);
+                "OK".to_owned()
+            }
+            _ => "E01".to_owned(),
+        }
+    }
+
+    fn read_memory(&self, range: &str) -> String {
+        match parse_range(range) {
+            Some((addr, len)) => {
+                let words: Vec<u16> = (0..len)
+                    .map(|i| self.processor.get_memory(addr.wrapping_add(i as u16)))
+                    .collect();
+                encode_words(&words)
+            }
+            None => "E01".to_owned(),
+        }
+    }
+
+    fn write_memory(&mut self, write: &str) -> String {
+        let mut parts = write.splitn(2, ':');
+        let range = parts.next().and_then(parse_range);
+        let words = parts.next().and_then(decode_words);
+        match (range, words) {
+            (Some((addr, len)), Some(words)) if words.len() == len => {
+                for (i, &word) in words.iter().enumerate() {
+                    self.processor.set_memory(addr.wrapping_add(i as u16), word);
+                }
+                "OK".to_owned()
+            }
+            _ => "E01".to_owned(),
+        }
+    }
+
+    /// Handles `Z`/`z` packets for software breakpoints (0) and watchpoints (2, 3 and 4)
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let mut parts = args.splitn(3, ',');
+        let kind = parts.next().and_then(parse_hex);
+        let addr = parts
+            .next()
+            .and_then(parse_hex)
+            .filter(|&addr| addr <= 0xffff);
+        let addr = match addr {
+            Some(addr) => addr as u16,
+            None => return "E01".to_owned(),
+        };
+        // Watchpoint lengths are in words, but breakpoint kinds are irrelevant
+        let len = parts.next().and_then(parse_hex).unwrap_or(1).max(1);
+        let end = addr.saturating_add((len - 1).min(0xffff) as u16);
+
+        let watch = match kind {
+            Some(0) => {
+                if insert {
+                    self.processor.add_breakpoint(addr);
+                } else {
+                    self.processor.remove_breakpoint(addr);
+                }
+                return "OK".to_owned();
+            }
+            Some(2) => WatchKind::Write,
+            Some(3) => WatchKind::Read,
+            Some(4) => WatchKind::ReadWrite,
+            _ => return String::new(),
+        };
+        if insert {
+            self.processor.add_watchpoint(addr..=end, watch);
+        } else {
+            self.processor.remove_watchpoint(addr);
+        }
+
+        "OK".to_owned()
+    }
+
+    fn step(&mut self) -> String {
+        match self.processor.step_instruction() {
+            Ok(()) => format!("S{:02x}", SIGTRAP),
+            Err(_) => format!("S{:02x}", SIGILL),
+        }
+    }
+
+    /// Runs until something stops the program or the debugger sends an interrupt
+    fn resume(&mut self) -> String {
+        loop {
+            match self.processor.run_until(CONTINUE_CHUNK) {
+                StopReason::CycleLimit => {}
+                StopReason::Breakpoint(_) => return format!("T{:02x}swbreak:;", SIGTRAP),
+                StopReason::MemoryWrite { addr, .. } => {
+                    return format!("T{:02x}watch:{:04x};", SIGTRAP, addr)
+                }
+                StopReason::MemoryRead { addr, .. } => {
+                    return format!("T{:02x}rwatch:{:04x};", SIGTRAP, addr)
+                }
+                StopReason::RegisterChanged { .. } => return format!("S{:02x}", SIGTRAP),
+                StopReason::Error(_) => return format!("S{:02x}", SIGILL),
+            }
+
+            let mut buf = [0; 1024];
+            let len = match self.connection.read_nonblocking(&mut buf) {
+                Ok(len) => len,
+                Err(_) => return format!("S{:02x}", SIGINT),
+            };
+            self.input.extend(&buf[..len]);
+            if let Some(i) = self.input.iter().position(|&byte| byte == 0x03) {
+                self.input.remove(i);
+                return format!("S{:02x}", SIGINT);
+            }
+        }
+    }
+}
+
+/// Waits for one debugger to connect to `addr` and serves it until it detaches
+pub fn serve_gdb<A: ToSocketAddrs>(processor: &mut Processor, addr: A) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    GdbStub::new(processor, stream).run()
+}
@@ -1,3 +1,4 @@
+use super::snapshot::SnapshotError;
 use super::Processor;
 use downcast_rs::{impl_downcast, Downcast};
 
@@ -5,6 +6,26 @@ pub trait HardwareDevice: Downcast {
     fn id(&self) -> u32;
     fn version(&self) -> u16;
     fn manufacturer(&self) -> u32;
-    fn handle_interrupt(&mut self, _processor: &Processor) {}
+    /// Called when the processor sends a HWI to this device. Devices can read and write the
+    /// processor's registers and memory, and charge extra cycles with `Processor::wait_cycles`.
+    fn handle_interrupt(&mut self, _processor: &mut Processor) {}
+    /// Called on every processor cycle, so devices can run alongside the CPU and raise
+    /// interrupts with `Processor::trigger_interrupt`
+    fn tick(&mut self, _processor: &mut Processor) {}
+    /// Serializes the device's internal state for `Processor::snapshot`. Devices with state should
+    /// write it with a `StateWriter`; the default saves nothing.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    /// Layout of the state returned by `save_state`, saved alongside it in snapshots. Bump it
+    /// whenever the layout changes so that `load_state` can tell older states apart.
+    fn state_version(&self) -> u16 {
+        1
+    }
+    /// Restores state previously returned by `save_state`, when a snapshot is restored. `version`
+    /// is the `state_version` it was saved with.
+    fn load_state(&mut self, _state: &[u8], _version: u16) -> Result<(), SnapshotError> {
+        Ok(())
+    }
 }
 impl_downcast!(HardwareDevice);
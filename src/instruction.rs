@@ -1,18 +1,27 @@
-use super::opcodes::*;
-use super::processor::Processor;
+use super::cycles;
+use super::error::ProcessorError;
+use super::opcodes::{self, *};
 use super::processor::Register::*;
+use super::processor::{Processor, Register};
 use super::value::Value;
-use std::mem;
 
 fn to_signed(val: u16) -> i16 {
-    unsafe { mem::transmute(val) }
+    val as i16
 }
 
 fn to_unsigned(val: i16) -> u16 {
-    unsafe { mem::transmute(val) }
+    val as u16
 }
 
-#[derive(Debug)]
+/// Where a value is written to
+#[derive(Copy, Clone, Debug)]
+enum Target {
+    Register(Register),
+    Memory(u16),
+    Literal,
+}
+
+#[derive(Copy, Clone, Debug)]
 pub struct Instruction {
     op: OpCode,
     b: Value,
@@ -27,9 +36,9 @@ impl From<u16> for Instruction {
 
         // Specials
         if op == 0x00 {
-            Instruction::new(SPL, Value::OpCode(b), Value::from(a))
+            Instruction::new(SPL, Value::OpCode(b), Value::from_bits(a))
         } else {
-            Instruction::new(op as OpCode, Value::from(b), Value::from(a))
+            Instruction::new(op as OpCode, Value::from_bits(b), Value::from_bits(a))
         }
     }
 }
@@ -39,6 +48,23 @@ impl Instruction {
         Instruction { op, b, a }
     }
 
+    pub fn op(&self) -> OpCode {
+        self.op
+    }
+
+    pub fn b(&self) -> Value {
+        self.b
+    }
+
+    pub fn a(&self) -> Value {
+        self.a
+    }
+
+    /// Number of words the instruction takes up, including the extra words for its values
+    pub fn size(&self) -> u16 {
+        1 + self.a.uses_next_word() as u16 + self.b.uses_next_word() as u16
+    }
+
     pub fn words(&self) -> Vec<u16> {
         let mut words = Vec::with_capacity(3);
         let a = self.a.get_a();
@@ -49,78 +75,104 @@ impl Instruction {
 
         // Larger literals use an extra word
         if a == 0x1F {
-            match self.a {
-                Value::Literal(val) => words.push(val),
-                //Value::NextWord => words.push(),
-                _ => {}
+            if let Value::Literal(val) = self.a {
+                words.push(val);
             }
         }
 
         words
     }
 
-    pub fn execute(&self, processor: &mut Processor) {
+    /// Cycles the instruction takes according to the spec, including looking up its values. Failed
+    /// tests and time spent by hardware take longer. Invalid instructions take 0 cycles.
+    pub fn cycles(&self) -> u16 {
+        let op_cycles = match (self.op, self.b) {
+            (SPL, Value::OpCode(op)) => cycles::special_cycles(op),
+            (SPL, _) => 0,
+            (op, _) => cycles::basic_cycles(op),
+        };
+        if op_cycles == 0 {
+            return 0;
+        }
+
+        op_cycles + cycles::value_cycles(self.a) + cycles::value_cycles(self.b)
+    }
+
+    /// Charges the cycles for the opcode. The first is the cycle the instruction starts on, and
+    /// values using the next word are charged as they're read.
+    fn charge(&self, processor: &mut Processor, op_cycles: u16) {
+        processor.cycle_wait += op_cycles.saturating_sub(1);
+    }
+
+    pub fn execute(&self, processor: &mut Processor) -> Result<(), ProcessorError> {
         match self.op {
             // Specials
             0x00 => self.execute_special(processor),
 
             // Setters
-            0x01..=0x0F | 0x1A..=0x1F => {
+            0x01..=0x0F | ADX | SBX | STI | STD => {
+                self.charge(processor, cycles::basic_cycles(self.op));
                 let a = self.get_a(processor);
-                self.set_b(processor, a);
+                self.set_b(processor, a)
             }
 
             // Conditionals
             0x10..=0x17 => {
+                self.charge(processor, cycles::basic_cycles(self.op));
                 let a = self.get_a(processor);
-                self.test_condition(processor, a);
+                self.test_condition(processor, a)
             }
 
             // Bad
-            _ => panic!("Invalid op code {}", self.op),
+            _ => Err(ProcessorError::InvalidOpCode(self.op)),
         }
     }
 
-    pub fn execute_special(&self, processor: &mut Processor) {
-        let a = self.get_a(processor);
+    pub fn execute_special(&self, processor: &mut Processor) -> Result<(), ProcessorError> {
         let op = self.peek_b(processor);
+        if opcodes::special_mnemonic(op).is_none() {
+            return Err(ProcessorError::InvalidSpecialOpCode(op));
+        }
+        self.charge(processor, cycles::special_cycles(op));
+
+        // These write to `a` instead of reading it
+        match op {
+            IAG => {
+                let value = processor.get_register(IA);
+                let target = self.target(processor, self.a, true);
+                self.write_target(processor, target, value);
+                return Ok(());
+            }
+            HWN => {
+                let hardware_count = processor.hardware_count();
+                let target = self.target(processor, self.a, true);
+                self.write_target(processor, target, hardware_count);
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let a = self.get_a(processor);
+        processor.trace_operands(Some(a), None);
         match op {
             JSR => {
-                processor.cycle_wait += 2;
                 let pc = processor.get_register(PC);
                 processor.push(pc);
                 processor.set_register(PC, a);
             }
             INT => {
-                processor.cycle_wait += 3;
                 processor.trigger_interrupt(a);
             }
-            IAG => {
-                let value = processor.get_register(IA);
-                self.set_value(processor, self.a, value);
-            }
             IAS => {
                 processor.set_register(IA, a);
             }
             RFI => {
-                processor.cycle_wait += 2;
                 processor.return_from_interrupt();
             }
             IAQ => {
-                processor.cycle_wait += 1;
-                if a == 0 {
-                    processor.is_queuing_interrupts = false;
-                } else {
-                    processor.is_queuing_interrupts = true;
-                }
-            }
-            HWN => {
-                processor.cycle_wait += 1;
-                let hardware_count = processor.hardware_count();
-                self.set_value(processor, self.a, hardware_count);
+                processor.is_queuing_interrupts = a != 0;
             }
             HWQ => {
-                processor.cycle_wait += 3;
                 if let Some(rc) = processor.get_hardware(a) {
                     if let Ok(hardware) = rc.try_borrow() {
                         let a = (hardware.id() & 0xFFFF) as u16;
@@ -134,7 +186,7 @@ impl Instruction {
                         processor.set_register(X, x);
                         processor.set_register(Y, y);
                     } else {
-                        panic!("TODO");
+                        return Err(ProcessorError::DeviceBusy(a));
                     }
                 } else {
                     processor.set_register(A, 0x00);
@@ -145,15 +197,18 @@ impl Instruction {
                 }
             }
             HWI => {
-                processor.cycle_wait += 3;
                 if let Some(rc) = processor.get_hardware(a) {
                     if let Ok(mut hardware) = rc.try_borrow_mut() {
                         hardware.handle_interrupt(processor);
+                    } else {
+                        return Err(ProcessorError::DeviceBusy(a));
                     }
                 }
             }
-            _ => panic!("Invalid special op code {}", op),
+            _ => return Err(ProcessorError::InvalidSpecialOpCode(op)),
         }
+
+        Ok(())
     }
 
     pub fn get_a(&self, processor: &mut Processor) -> u16 {
@@ -161,11 +216,12 @@ impl Instruction {
             Value::Register(reg) => processor.get_register(reg),
             Value::RegisterPointer(reg) => {
                 let addr = processor.get_register(reg);
-                processor.memory[addr]
+                processor.read_memory(addr)
             }
             Value::RegisterPointerOffset(reg) => {
-                let addr = processor.get_register(reg) + processor.next_word();
-                processor.memory[addr]
+                let offset = processor.next_word();
+                let addr = processor.get_register(reg).wrapping_add(offset);
+                processor.read_memory(addr)
             }
             Value::Push | Value::Pop => {
                 // A is always POP
@@ -173,12 +229,13 @@ impl Instruction {
             }
             Value::Peek => processor.peek(),
             Value::Pick => {
-                let addr = processor.get_register(SP) + processor.next_word();
-                processor.memory[addr]
+                let offset = processor.next_word();
+                let addr = processor.get_register(SP).wrapping_add(offset);
+                processor.read_memory(addr)
             }
             Value::NextWordPointer => {
                 let addr = processor.next_word();
-                processor.memory[addr]
+                processor.read_memory(addr)
             }
             Value::NextWord => processor.next_word(),
             Value::Literal(literal) => literal,
@@ -191,25 +248,27 @@ impl Instruction {
             Value::Register(reg) => processor.get_register(reg),
             Value::RegisterPointer(reg) => {
                 let addr = processor.get_register(reg);
-                processor.memory[addr]
+                processor.read_memory(addr)
             }
             Value::RegisterPointerOffset(reg) => {
-                let addr = processor.get_register(reg) + processor.next_word();
-                processor.memory[addr]
+                let offset = processor.next_word();
+                let addr = processor.get_register(reg).wrapping_add(offset);
+                processor.read_memory(addr)
             }
             Value::Push | Value::Pop => {
                 // B is always PUSH
                 let addr = processor.get_register(SP).wrapping_sub(1);
-                processor.memory[addr]
+                processor.read_memory(addr)
             }
             Value::Peek => processor.peek(),
             Value::Pick => {
-                let addr = processor.get_register(SP) + processor.next_word();
-                processor.memory[addr]
+                let offset = processor.next_word();
+                let addr = processor.get_register(SP).wrapping_add(offset);
+                processor.read_memory(addr)
             }
             Value::NextWordPointer => {
                 let addr = processor.next_word();
-                processor.memory[addr]
+                processor.read_memory(addr)
             }
             Value::NextWord => processor.next_word(),
             Value::Literal(literal) => literal,
@@ -223,25 +282,27 @@ impl Instruction {
             Value::Register(reg) => processor.get_register(reg),
             Value::RegisterPointer(reg) => {
                 let addr = processor.get_register(reg);
-                processor.memory[addr]
+                processor.read_memory(addr)
             }
             Value::RegisterPointerOffset(reg) => {
-                let addr = processor.get_register(reg) + processor.peek_next_word();
-                processor.memory[addr]
+                let offset = processor.peek_next_word();
+                let addr = processor.get_register(reg).wrapping_add(offset);
+                processor.read_memory(addr)
             }
             Value::Push | Value::Pop => {
                 // B is always PUSH
                 let addr = processor.get_register(SP).wrapping_sub(1);
-                processor.memory[addr]
+                processor.read_memory(addr)
             }
             Value::Peek => processor.peek(),
             Value::Pick => {
-                let addr = processor.get_register(SP) + processor.peek_next_word();
-                processor.memory[addr]
+                let offset = processor.peek_next_word();
+                let addr = processor.get_register(SP).wrapping_add(offset);
+                processor.read_memory(addr)
             }
             Value::NextWordPointer => {
                 let addr = processor.peek_next_word();
-                processor.memory[addr]
+                processor.read_memory(addr)
             }
             Value::NextWord => processor.peek_next_word(),
             Value::Literal(literal) => literal,
@@ -249,14 +310,20 @@ impl Instruction {
         }
     }
 
-    pub fn set_b(&self, processor: &mut Processor, a: u16) {
-        // Get current `b` value to apply the operation to
-        let b = self.peek_b(processor);
+    pub fn set_b(&self, processor: &mut Processor, a: u16) -> Result<(), ProcessorError> {
+        // Get current `b` value to apply the operation to. SET never reads it, so it mustn't trip
+        // read watchpoints.
+        let b = if self.op == SET {
+            None
+        } else {
+            Some(self.peek_b(processor))
+        };
+        processor.trace_operands(Some(a), b);
+        let b = b.unwrap_or(0);
         let mut ex = processor.get_register(EX);
         let new_value = match self.op {
             SET => a,
             ADD => {
-                processor.cycle_wait += 1;
                 let (value, overflowed) = b.overflowing_add(a);
                 if overflowed {
                     ex = 0x0001;
@@ -267,7 +334,6 @@ impl Instruction {
                 value
             }
             SUB => {
-                processor.cycle_wait += 1;
                 let (value, overflowed) = b.overflowing_sub(a);
                 if overflowed {
                     ex = 0xFFFF;
@@ -278,20 +344,16 @@ impl Instruction {
                 value
             }
             MUL => {
-                processor.cycle_wait += 1;
                 ex = (((b as u32 * a as u32) >> 16) & 0xFFFF) as u16;
                 b.wrapping_mul(a)
             }
             MLI => {
-                processor.cycle_wait += 1;
                 let signed_b = to_signed(b);
                 let signed_a = to_signed(a);
                 ex = to_unsigned((((signed_b as i32 * signed_a as i32) >> 16) & 0xFFFF) as i16);
                 to_unsigned(signed_b.wrapping_mul(signed_a))
             }
             DIV => {
-                processor.cycle_wait += 2;
-
                 if a == 0 {
                     ex = 0;
                     0
@@ -301,8 +363,6 @@ impl Instruction {
                 }
             }
             DVI => {
-                processor.cycle_wait += 2;
-
                 if a == 0 {
                     ex = 0;
                     0
@@ -316,7 +376,6 @@ impl Instruction {
                 }
             }
             MOD => {
-                processor.cycle_wait += 2;
                 if a == 0 {
                     0
                 } else {
@@ -324,7 +383,6 @@ impl Instruction {
                 }
             }
             MDI => {
-                processor.cycle_wait += 2;
                 if a == 0 {
                     0
                 } else {
@@ -336,22 +394,23 @@ impl Instruction {
             AND => b & a,
             BOR => b | a,
             XOR => b ^ a,
+            // Shifting by 16 or more pushes everything out, into EX first
             SHR => {
-                ex = (((b as u32) << 16) >> a) as u16;
-                b >> a
+                let shifted = ((b as u32) << 16).checked_shr(a as u32).unwrap_or(0);
+                ex = shifted as u16;
+                (shifted >> 16) as u16
             }
             ASR => {
-                let signed_b = to_signed(b);
-                let signed_a = to_signed(a);
-                ex = (((signed_b as u32) << 16) >> signed_a) as u16;
-                to_unsigned(signed_b >> signed_a)
+                let shifted = ((to_signed(b) as i32) << 16) >> a.min(31);
+                ex = shifted as u16;
+                (shifted >> 16) as u16
             }
             SHL => {
-                ex = (((b as u32) << a) >> 16) as u16;
-                b << a
+                let shifted = (b as u32).checked_shl(a as u32).unwrap_or(0);
+                ex = (shifted >> 16) as u16;
+                shifted as u16
             }
             ADX => {
-                processor.cycle_wait += 2;
                 let (value1, overflowed1) = b.overflowing_add(a);
                 let (value2, overflowed2) = value1.overflowing_add(ex);
                 if overflowed1 || overflowed2 {
@@ -362,7 +421,6 @@ impl Instruction {
                 value2
             }
             SBX => {
-                processor.cycle_wait += 2;
                 let (value1, overflowed1) = b.overflowing_sub(a);
                 let (value2, overflowed2) = value1.overflowing_add(ex);
                 if overflowed1 || overflowed2 {
@@ -373,86 +431,99 @@ impl Instruction {
                 value2
             }
             STI => {
-                processor.cycle_wait += 1;
                 processor.inc(I);
                 processor.inc(J);
                 a
             }
             STD => {
-                processor.cycle_wait += 1;
                 processor.dec(I);
                 processor.dec(J);
                 a
             }
-            _ => panic!("Invalid op code {}", self.op),
+            _ => return Err(ProcessorError::InvalidOpCode(self.op)),
         };
 
         processor.set_register(EX, ex);
 
         self.set_value(processor, self.b, new_value);
+
+        Ok(())
     }
 
+    /// Writes `value` to `target` as if it were `b`
     pub fn set_value(&self, processor: &mut Processor, target: Value, value: u16) {
-        match target {
-            Value::Register(reg) => {
-                processor.registers[reg as usize] = value;
-            }
-            Value::RegisterPointer(reg) => {
-                let addr = processor.get_register(reg);
-                processor.memory[addr] = value;
-            }
+        let target = self.target(processor, target, false);
+        self.write_target(processor, target, value);
+    }
+
+    /// Works out where a value will be written, reading its next word if it has one. Stack
+    /// access is POP when `is_a` and PUSH otherwise.
+    fn target(&self, processor: &mut Processor, value: Value, is_a: bool) -> Target {
+        match value {
+            Value::Register(reg) => Target::Register(reg),
+            Value::RegisterPointer(reg) => Target::Memory(processor.get_register(reg)),
             Value::RegisterPointerOffset(reg) => {
                 let offset = processor.next_word();
-                let addr = processor.get_register(reg).wrapping_add(offset);
-                processor.memory[addr] = value;
+                Target::Memory(processor.get_register(reg).wrapping_add(offset))
+            }
+            Value::Push | Value::Pop if is_a => {
+                let addr = processor.get_register(SP);
+                processor.inc(SP);
+                Target::Memory(addr)
             }
             Value::Push | Value::Pop => {
-                // B is always PUSH
                 processor.dec(SP);
-                let addr = processor.get_register(SP);
-                processor.memory[addr] = value;
+                Target::Memory(processor.get_register(SP))
             }
-            Value::Peek => {}
-            Value::Pick => {}
-            Value::NextWordPointer => {
-                let addr = processor.next_word();
-                processor.set_memory(addr, value);
+            Value::Peek => Target::Memory(processor.get_register(SP)),
+            Value::Pick => {
+                let offset = processor.next_word();
+                Target::Memory(processor.get_register(SP).wrapping_add(offset))
+            }
+            Value::NextWordPointer => Target::Memory(processor.next_word()),
+            Value::NextWord => {
+                // The literal still has to be read past
+                processor.next_word();
+                Target::Literal
             }
-            Value::NextWord => {}
-            Value::Literal(_) => {}
-            Value::OpCode(_) => {}
+            Value::Literal(_) | Value::OpCode(_) => Target::Literal,
         }
     }
 
-    pub fn test_condition(&self, processor: &mut Processor, a: u16) {
+    fn write_target(&self, processor: &mut Processor, target: Target, value: u16) {
+        match target {
+            Target::Register(reg) => processor.set_register(reg, value),
+            Target::Memory(addr) => processor.write_memory(addr, value),
+            // Writing to a literal fails silently
+            Target::Literal => {}
+        }
+    }
+
+    pub fn test_condition(&self, processor: &mut Processor, a: u16) -> Result<(), ProcessorError> {
         let b = self.get_b(processor);
+        processor.trace_operands(Some(a), Some(b));
         match self.op {
             IFB => {
-                processor.cycle_wait += 1;
                 if b & a == 0 {
                     self.condition_failure(processor);
                 }
             }
             IFC => {
-                processor.cycle_wait += 1;
                 if b & a != 0 {
                     self.condition_failure(processor);
                 }
             }
             IFE => {
-                processor.cycle_wait += 1;
                 if b != a {
                     self.condition_failure(processor);
                 }
             }
             IFN => {
-                processor.cycle_wait += 1;
                 if b == a {
                     self.condition_failure(processor);
                 }
             }
             IFG => {
-                processor.cycle_wait += 1;
                 if b <= a {
                     self.condition_failure(processor);
                 }
@@ -460,14 +531,11 @@ impl Instruction {
             IFA => {
                 let signed_b = to_signed(b);
                 let signed_a = to_signed(a);
-
-                processor.cycle_wait += 1;
                 if signed_b <= signed_a {
                     self.condition_failure(processor);
                 }
             }
             IFL => {
-                processor.cycle_wait += 1;
                 if b >= a {
                     self.condition_failure(processor);
                 }
@@ -475,44 +543,30 @@ impl Instruction {
             IFU => {
                 let signed_b = to_signed(b);
                 let signed_a = to_signed(a);
-
-                processor.cycle_wait += 1;
                 if signed_b >= signed_a {
                     self.condition_failure(processor);
                 }
             }
-            _ => panic!("Invalid op code {}", self.op),
+            _ => return Err(ProcessorError::InvalidOpCode(self.op)),
         };
+
+        Ok(())
     }
 
+    /// Skips the next instruction, along with any conditionals chained before it
     pub fn condition_failure(&self, processor: &mut Processor) {
-        // Condition failed, skip next instruction
-        let addr = processor.get_register(PC);
-        let Instruction { op, b, a } = processor.memory.get_instruction(addr);
+        processor.cycle_wait += cycles::SKIP_CYCLES;
+        // Bounded, in case memory is nothing but conditionals
+        for _ in 0..0x10000 {
+            let addr = processor.get_register(PC);
+            let instruction = processor.decode(addr);
+            processor.set_register(PC, addr.wrapping_add(instruction.size()));
 
-        // Skip extra word in longer instructions
-        match b {
-            Value::RegisterPointerOffset(_) | Value::NextWordPointer | Value::NextWord => {
-                processor.inc(PC);
-                processor.cycle_wait += 1;
-            }
-            _ => {}
-        }
-        match a {
-            Value::RegisterPointerOffset(_) | Value::NextWordPointer | Value::NextWord => {
-                processor.inc(PC);
-                processor.cycle_wait += 1;
+            if let 0x10..=0x17 = instruction.op {
+                processor.cycle_wait += cycles::SKIP_CYCLES;
+            } else {
+                break;
             }
-            _ => {}
-        }
-        match op {
-            // Chaining IFn conditions
-            0x10..=0x17 => {
-                processor.inc(PC);
-                processor.cycle_wait += 1;
-            }
-            _ => {}
         }
-        processor.inc(PC);
     }
 }
@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+
+/// Number of instructions the debugger keeps history for by default
+pub const DEFAULT_JOURNAL_CAPACITY: usize = 100_000;
+
+/// Everything needed to undo one instruction, along with the wait cycles and interrupts that
+/// followed it
+#[derive(Clone, Debug)]
+pub struct JournalEntry {
+    /// Address of the instruction
+    pub pc: u16,
+    /// Registers as they were before the instruction
+    pub registers: [u16; 12],
+    pub cycle: usize,
+    pub cycle_wait: u16,
+    pub is_queuing_interrupts: bool,
+    pub is_on_fire: bool,
+    pub interrupt_queue: VecDeque<u16>,
+    /// Memory words that were overwritten, as `(addr, old value)`, oldest first
+    pub memory: Vec<(u16, u16)>,
+}
+
+/// Bounded history of executed instructions, used by `Processor::step_back`.
+/// Once full, the oldest entries are dropped.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    entries: VecDeque<JournalEntry>,
+    capacity: usize,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Journal {
+        Journal {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Entries from oldest to newest
+    pub fn entries(&self) -> impl Iterator<Item = &JournalEntry> {
+        self.entries.iter()
+    }
+
+    pub fn last(&self) -> Option<&JournalEntry> {
+        self.entries.back()
+    }
+
+    pub(crate) fn begin(&mut self, entry: JournalEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub(crate) fn record_write(&mut self, addr: u16, old: u16) {
+        if let Some(entry) = self.entries.back_mut() {
+            entry.memory.push((addr, old));
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<JournalEntry> {
+        self.entries.pop_back()
+    }
+}
@@ -0,0 +1,188 @@
+use super::hardware::HardwareDevice;
+use super::processor::Processor;
+use super::processor::Register::*;
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
+use std::collections::VecDeque;
+
+pub const KEY_BACKSPACE: u16 = 0x10;
+pub const KEY_RETURN: u16 = 0x11;
+pub const KEY_INSERT: u16 = 0x12;
+pub const KEY_DELETE: u16 = 0x13;
+pub const KEY_UP: u16 = 0x80;
+pub const KEY_DOWN: u16 = 0x81;
+pub const KEY_LEFT: u16 = 0x82;
+pub const KEY_RIGHT: u16 = 0x83;
+pub const KEY_SHIFT: u16 = 0x90;
+pub const KEY_CONTROL: u16 = 0x91;
+
+const BUFFER_SIZE: usize = 64;
+
+pub fn is_valid_key(key: u16) -> bool {
+    matches!(
+        key,
+        KEY_BACKSPACE..=KEY_DELETE | 0x20..=0x7F | KEY_UP..=KEY_RIGHT | KEY_SHIFT | KEY_CONTROL
+    )
+}
+
+/// Converts a host character into a key number, if the keyboard has a key for it
+pub fn key_from_char(c: char) -> Option<u16> {
+    match c {
+        '\x08' | '\x7F' => Some(KEY_BACKSPACE),
+        '\n' | '\r' => Some(KEY_RETURN),
+        ' '..='~' => Some(c as u16),
+        _ => None,
+    }
+}
+
+/// Generic Keyboard with a buffer of typed keys
+pub struct Keyboard {
+    pub interrupt_message: u16,
+    buffer: VecDeque<u16>,
+    pressed: [bool; 0x100],
+    has_changed: bool,
+}
+
+impl Default for Keyboard {
+    fn default() -> Keyboard {
+        Keyboard::new()
+    }
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard {
+            interrupt_message: 0,
+            buffer: VecDeque::with_capacity(BUFFER_SIZE),
+            pressed: [false; 0x100],
+            has_changed: false,
+        }
+    }
+
+    /// Adds a key to the typed buffer. Keys are dropped when the buffer is full.
+    pub fn type_key(&mut self, key: u16) {
+        if !is_valid_key(key) || self.buffer.len() >= BUFFER_SIZE {
+            return;
+        }
+        self.buffer.push_back(key);
+        self.has_changed = true;
+    }
+
+    pub fn type_str(&mut self, text: &str) {
+        for key in text.chars().filter_map(key_from_char) {
+            self.type_key(key);
+        }
+    }
+
+    pub fn press_key(&mut self, key: u16) {
+        if !is_valid_key(key) {
+            return;
+        }
+        self.pressed[key as usize] = true;
+        self.has_changed = true;
+    }
+
+    pub fn release_key(&mut self, key: u16) {
+        if !is_valid_key(key) {
+            return;
+        }
+        self.pressed[key as usize] = false;
+        self.has_changed = true;
+    }
+
+    pub fn is_pressed(&self, key: u16) -> bool {
+        is_valid_key(key) && self.pressed[key as usize]
+    }
+
+    /// Removes the oldest typed key from the buffer, or returns 0 if it's empty
+    pub fn next_key(&mut self) -> u16 {
+        self.buffer.pop_front().unwrap_or(0)
+    }
+
+    pub fn buffer_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn clear_buffer(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Returns the interrupt message to trigger if keys have been pressed, released, or typed
+    /// since the last call
+    pub fn take_interrupt(&mut self) -> Option<u16> {
+        let has_changed = self.has_changed;
+        self.has_changed = false;
+        if has_changed && self.interrupt_message > 0 {
+            Some(self.interrupt_message)
+        } else {
+            None
+        }
+    }
+}
+
+impl HardwareDevice for Keyboard {
+    fn id(&self) -> u32 {
+        0x30CF7406
+    }
+    fn version(&self) -> u16 {
+        0x0001
+    }
+    fn manufacturer(&self) -> u32 {
+        0x00000000
+    }
+    fn handle_interrupt(&mut self, processor: &mut Processor) {
+        let op = processor.get_register(A);
+        let param = processor.get_register(B);
+
+        match op {
+            0x00 => self.clear_buffer(),
+            0x01 => {
+                let key = self.next_key();
+                processor.set_register(C, key);
+            }
+            0x02 => {
+                let is_pressed = self.is_pressed(param);
+                processor.set_register(C, is_pressed as u16);
+            }
+            0x03 => self.interrupt_message = param,
+            _ => {}
+        }
+    }
+    fn tick(&mut self, processor: &mut Processor) {
+        if let Some(message) = self.take_interrupt() {
+            processor.trigger_interrupt(message);
+        }
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let pressed: Vec<u16> = (0..0x100).filter(|&key| self.pressed[key as usize]).collect();
+        let buffer: Vec<u16> = self.buffer.iter().cloned().collect();
+
+        let mut writer = StateWriter::new();
+        writer.write_u16(self.interrupt_message);
+        writer.write_bool(self.has_changed);
+        writer.write_words(&buffer);
+        writer.write_words(&pressed);
+        writer.into_bytes()
+    }
+    fn load_state(&mut self, state: &[u8], version: u16) -> Result<(), SnapshotError> {
+        if version != 1 {
+            return Err(SnapshotError::InvalidDeviceState(self.id()));
+        }
+        let mut reader = StateReader::new(state);
+        let interrupt_message = reader.read_u16()?;
+        let has_changed = reader.read_bool()?;
+        let buffer = reader.read_words()?;
+        let pressed = reader.read_words()?;
+        if buffer.len() > BUFFER_SIZE || pressed.iter().any(|&key| key >= 0x100) {
+            return Err(SnapshotError::InvalidDeviceState(self.id()));
+        }
+
+        self.interrupt_message = interrupt_message;
+        self.has_changed = has_changed;
+        self.buffer = buffer.into_iter().collect();
+        self.pressed = [false; 0x100];
+        for key in pressed {
+            self.pressed[key as usize] = true;
+        }
+        Ok(())
+    }
+}
@@ -1,18 +1,57 @@
+mod assembler;
+mod clock;
+pub mod cycles;
+mod dap;
+mod decode_cache;
+mod disassembler;
+mod error;
+mod framebuffer;
+mod gdb;
 mod hardware;
 mod instruction;
+mod journal;
+mod json;
+pub mod keyboard;
 mod memory;
 mod monitor;
 pub mod opcodes;
+mod pacing;
 mod processor;
 mod program;
+mod screenshot;
+mod snapshot;
+mod trace;
 mod value;
+mod watch;
+pub use self::assembler::{assemble, parse_literal, Assembler, AssemblerError, AssemblerErrorKind};
+pub use self::clock::{Clock, PROCESSOR_FREQUENCY};
+pub use self::dap::DapServer;
+pub use self::disassembler::{disassemble, disassemble_at, Disassembly};
+pub use self::error::ProcessorError;
+pub use self::framebuffer::{
+    color_to_rgb, Framebuffer, CHAR_HEIGHT, CHAR_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+pub use self::gdb::{serve_gdb, Connection, GdbStub, StdioConnection, TARGET_XML};
 pub use self::hardware::HardwareDevice;
 pub use self::instruction::Instruction;
-pub use self::monitor::Monitor;
+pub use self::journal::{Journal, JournalEntry, DEFAULT_JOURNAL_CAPACITY};
+pub use self::keyboard::Keyboard;
+pub use self::monitor::{Monitor, BLINK_CYCLES, DEFAULT_BORDER_SIZE, STARTUP_CYCLES};
+pub use self::pacing::{HostClock, Pacer, Speed, SystemClock, UNTHROTTLED_CHUNK};
 pub use self::processor::{Processor, Register};
 pub use self::value::Value;
 pub use self::program::Program;
-pub use self::memory::Memory;
+pub use self::memory::{Endianness, ImageError, LoadError, Memory};
+pub use self::screenshot::{
+    assert_matches_golden, diff_frames, ScreenshotError, UPDATE_GOLDEN_VAR,
+};
+pub use self::snapshot::{
+    DeviceState, Snapshot, SnapshotError, StateReader, StateWriter, SNAPSHOT_MAGIC, SNAPSHOT_VERSION,
+};
+pub use self::trace::{
+    read_binary_trace, BinaryTrace, TextTrace, TraceRecord, TraceSink, TRACE_MAGIC, TRACE_VERSION,
+};
+pub use self::watch::{RunSummary, StopReason, WatchKind, Watchpoint};
 
 #[cfg(test)]
 mod tests;
@@ -1,8 +1,131 @@
+use super::assembler::{Assembler, AssemblerError};
 use super::instruction::Instruction;
 use super::program::Program;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::ops::{Index, IndexMut};
+use std::path::Path;
+
+/// Byte order of words in a binary image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Endianness {
+    Big,
+    Little,
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    /// Images must be made of whole 16-bit words
+    OddByteCount(usize),
+    /// The image doesn't fit between its base address and the end of memory
+    TooLarge {
+        addr: u16,
+        words: usize,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::OddByteCount(len) => {
+                write!(f, "image has an odd number of bytes ({})", len)
+            }
+            ImageError::TooLarge { addr, words } => write!(
+                f,
+                "image of {} words doesn't fit in memory at {:#06x}",
+                words, addr
+            ),
+            ImageError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImageError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> ImageError {
+        ImageError::Io(err)
+    }
+}
+
+/// Why `Memory::load_program_file` failed
+#[derive(Debug)]
+pub enum LoadError {
+    /// Source is assembled to run from address 0, so it can't be loaded anywhere else
+    MovedSource(u16),
+    Assembler(AssemblerError),
+    Image(ImageError),
+    Io(io::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::MovedSource(addr) => write!(
+                f,
+                "assembled programs start at 0x0000 and can't be loaded at {:#06x}",
+                addr
+            ),
+            LoadError::Assembler(err) => write!(f, "{}", err),
+            LoadError::Image(err) => write!(f, "{}", err),
+            LoadError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Assembler(err) => Some(err),
+            LoadError::Image(err) => Some(err),
+            LoadError::Io(err) => Some(err),
+            LoadError::MovedSource(_) => None,
+        }
+    }
+}
+
+impl From<AssemblerError> for LoadError {
+    fn from(err: AssemblerError) -> LoadError {
+        LoadError::Assembler(err)
+    }
+}
+
+impl From<ImageError> for LoadError {
+    fn from(err: ImageError) -> LoadError {
+        LoadError::Image(err)
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        LoadError::Io(err)
+    }
+}
+
+fn check_fits(addr: u16, words: usize) -> Result<(), ImageError> {
+    if addr as usize + words > 0x10000 {
+        Err(ImageError::TooLarge { addr, words })
+    } else {
+        Ok(())
+    }
+}
 
 pub struct Memory([u16; 0x10000]);
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
 impl Memory {
     pub fn new() -> Memory {
         Memory([0; 0x10000])
@@ -16,14 +139,139 @@ impl Memory {
         self[addr]
     }
 
+    /// All 0x10000 words of memory
+    pub fn words(&self) -> &[u16] {
+        &self.0
+    }
+
+    pub fn words_mut(&mut self) -> &mut [u16] {
+        &mut self.0
+    }
+
     pub fn get_instruction(&self, addr: u16) -> Instruction {
         Instruction::from(self[addr])
     }
 
+    /// Copies `program` into memory at `addr`, wrapping around past 0xffff like the processor does
     pub fn load_program(&mut self, addr: u16, program: &Program) {
         for (i, &word) in program.words().iter().enumerate() {
-            self[addr + i as u16] = word;
+            self[addr.wrapping_add(i as u16)] = word;
+        }
+    }
+
+    /// Loads a binary image into memory starting at `addr`, returning the number of words loaded
+    pub fn load_bytes(
+        &mut self,
+        addr: u16,
+        bytes: &[u8],
+        endianness: Endianness,
+    ) -> Result<usize, ImageError> {
+        if !bytes.len().is_multiple_of(2) {
+            return Err(ImageError::OddByteCount(bytes.len()));
+        }
+        let words = bytes.len() / 2;
+        check_fits(addr, words)?;
+
+        for (i, pair) in bytes.chunks(2).enumerate() {
+            let pair = [pair[0], pair[1]];
+            let word = match endianness {
+                Endianness::Big => u16::from_be_bytes(pair),
+                Endianness::Little => u16::from_le_bytes(pair),
+            };
+            self.0[addr as usize + i] = word;
+        }
+
+        Ok(words)
+    }
+
+    pub fn load_reader<R: Read>(
+        &mut self,
+        addr: u16,
+        reader: &mut R,
+        endianness: Endianness,
+    ) -> Result<usize, ImageError> {
+        let mut bytes = Vec::with_capacity(0x20000);
+        reader.read_to_end(&mut bytes)?;
+        self.load_bytes(addr, &bytes, endianness)
+    }
+
+    pub fn load_file<P: AsRef<Path>>(
+        &mut self,
+        addr: u16,
+        path: P,
+        endianness: Endianness,
+    ) -> Result<usize, ImageError> {
+        let mut file = File::open(path)?;
+        self.load_reader(addr, &mut file, endianness)
+    }
+
+    /// Assembles a `.dasm` source file into memory, or loads any other file as a binary image
+    /// starting at `addr`. Returns the assembler for source files so their labels and line
+    /// numbers can be looked up.
+    pub fn load_program_file<P: AsRef<Path>>(
+        &mut self,
+        addr: u16,
+        path: P,
+        endianness: Endianness,
+    ) -> Result<Option<Assembler>, LoadError> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|ext| ext == "dasm") {
+            if addr != 0 {
+                return Err(LoadError::MovedSource(addr));
+            }
+            let source = fs::read_to_string(path)?;
+            let mut assembler = Assembler::new();
+            let program = assembler.assemble(&source)?;
+            self.load_program(addr, &program);
+            Ok(Some(assembler))
+        } else {
+            self.load_file(addr, path, endianness)?;
+            Ok(None)
+        }
+    }
+
+    /// Dumps `words` words of memory starting at `addr` as a binary image
+    pub fn dump_bytes(
+        &self,
+        addr: u16,
+        words: usize,
+        endianness: Endianness,
+    ) -> Result<Vec<u8>, ImageError> {
+        check_fits(addr, words)?;
+
+        let mut bytes = Vec::with_capacity(words * 2);
+        for &word in &self.0[addr as usize..addr as usize + words] {
+            let pair = match endianness {
+                Endianness::Big => word.to_be_bytes(),
+                Endianness::Little => word.to_le_bytes(),
+            };
+            bytes.extend_from_slice(&pair);
         }
+
+        Ok(bytes)
+    }
+
+    pub fn save_writer<W: Write>(
+        &self,
+        addr: u16,
+        words: usize,
+        writer: &mut W,
+        endianness: Endianness,
+    ) -> Result<(), ImageError> {
+        let bytes = self.dump_bytes(addr, words, endianness)?;
+        writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    pub fn save_file<P: AsRef<Path>>(
+        &self,
+        addr: u16,
+        words: usize,
+        path: P,
+        endianness: Endianness,
+    ) -> Result<(), ImageError> {
+        let mut file = File::create(path)?;
+        self.save_writer(addr, words, &mut file, endianness)
     }
 }
 impl Index<u16> for Memory {
@@ -1,6 +1,11 @@
+use super::clock::PROCESSOR_FREQUENCY;
+use super::framebuffer::{
+    color_to_rgb, Framebuffer, CHAR_HEIGHT, CHAR_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
 use super::hardware::HardwareDevice;
 use super::processor::Processor;
 use super::processor::Register::*;
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
 
 const DEFAULT_FONT: [u16; 256] = [
     0x000f, 0x0808, 0x080f, 0x0808, 0x08f8, 0x0808, 0x00ff, 0x0808, 0x0808, 0x0808, 0x08ff, 0x0808,
@@ -32,11 +37,72 @@ const DEFAULT_PALETTE: [u16; 16] = [
     0xf5f, 0xff5, 0xfff,
 ];
 
+/// How long the screen shows its splash after being connected, about a second
+pub const STARTUP_CYCLES: usize = PROCESSOR_FREQUENCY;
+
+/// Border drawn around the display area by default, in pixels
+pub const DEFAULT_BORDER_SIZE: usize = 4;
+
+/// Blinking characters are shown for this many cycles, then hidden for as many
+pub const BLINK_CYCLES: usize = PROCESSOR_FREQUENCY / 2;
+
+/// The processor is halted while the default font or palette is dumped to memory
+const MEM_DUMP_FONT_CYCLES: u16 = 256;
+const MEM_DUMP_PALETTE_CYCLES: u16 = 16;
+
+/// Shown while the screen starts up
+const SPLASH: [&str; 12] = [
+    "",
+    "",
+    "",
+    "             \\ |  ___",
+    "           |\\ \\|  ___",
+    "           | \\",
+    "",
+    "         NYA ELEKTRISKA",
+    "     innovation information",
+    "",
+    "",
+    "",
+];
+const SPLASH_COLORS: u16 = 0xe100;
+
+fn splash_cell(index: u16) -> u16 {
+    let row = SPLASH[(index / 32) as usize % SPLASH.len()];
+    let c = row
+        .as_bytes()
+        .get((index % 32) as usize)
+        .cloned()
+        .unwrap_or(b' ');
+    SPLASH_COLORS | c as u16
+}
+
+/// The LEM1802 described in `docs/lem1802.txt`, plus the parts of the real screen that spec
+/// leaves out:
+///
+/// - Interrupt 4, MEM_DUMP_FONT, writes the default font to memory starting at B and halts the
+///   processor for 256 cycles.
+/// - Interrupt 5, MEM_DUMP_PALETTE, writes the default palette to memory starting at B and halts
+///   the processor for 16 cycles.
+/// - Nothing is shown while the screen is disconnected.
+/// - After being connected the screen shows the Nya Elektriska splash for `STARTUP_CYCLES`, using
+///   the default font and palette, before showing video ram.
 pub struct Monitor {
+    /// Start of video ram, or 0 when the screen is disconnected
     pub screen_addr: u16,
     pub font_addr: u16,
     pub palette_addr: u16,
     pub border_color: u16,
+    /// Width of the border drawn around the screen by every renderer, in pixels
+    pub border_size: usize,
+    /// Cycle the screen was last connected on
+    connected_at: Option<usize>,
+}
+
+impl Default for Monitor {
+    fn default() -> Monitor {
+        Monitor::new()
+    }
 }
 
 impl Monitor {
@@ -46,24 +112,135 @@ impl Monitor {
             font_addr: 0x0,
             palette_addr: 0x0,
             border_color: 0x0,
+            border_size: DEFAULT_BORDER_SIZE,
+            connected_at: None,
         }
     }
 
-    pub fn render_ansi(&self, processor: &Processor) -> String {
-        let mut output = "".to_owned();
-        output += "\x1b[0;0H";
+    pub fn is_connected(&self) -> bool {
+        self.screen_addr != 0
+    }
+
+    /// Whether the screen is still showing its splash after being connected
+    pub fn is_starting_up(&self, processor: &Processor) -> bool {
+        self.is_connected()
+            && self
+                .connected_at
+                .is_some_and(|cycle| processor.cycle().wrapping_sub(cycle) < STARTUP_CYCLES)
+    }
+
+    /// The word shown in cell `index`, or None when the screen is disconnected
+    pub fn get_cell(&self, processor: &Processor, index: u16) -> Option<u16> {
+        if !self.is_connected() {
+            None
+        } else if self.is_starting_up(processor) {
+            Some(splash_cell(index))
+        } else {
+            Some(processor.get_memory(self.screen_addr.wrapping_add(index)))
+        }
+    }
+
+    /// The 12 bit color for palette entry `index`
+    pub fn get_color(&self, processor: &Processor, index: u8) -> u16 {
+        let index = index & 0xF;
+        if self.palette_addr > 0 && !self.is_starting_up(processor) {
+            processor.get_memory(self.palette_addr.wrapping_add(index as u16))
+        } else {
+            DEFAULT_PALETTE[index as usize]
+        }
+    }
+
+    /// Word `addr` of the font, where each character takes up 2 words
+    pub fn get_font_word(&self, processor: &Processor, addr: u16) -> u16 {
+        let addr = addr & 0xFF;
+        if self.font_addr > 0 && !self.is_starting_up(processor) {
+            processor.get_memory(self.font_addr.wrapping_add(addr))
+        } else {
+            DEFAULT_FONT[addr as usize]
+        }
+    }
+
+    /// Whether blinking characters are currently shown. This follows the processor's cycle
+    /// count rather than the host's clock, so a frame rendered at a given cycle is always the same.
+    pub fn is_blink_visible(&self, processor: &Processor) -> bool {
+        (processor.cycle() / BLINK_CYCLES).is_multiple_of(2)
+    }
+
+    /// Foreground and background palette indices for `cell`. A blinking character's foreground
+    /// takes the background color while it's hidden.
+    pub fn get_cell_colors(&self, processor: &Processor, cell: u16) -> (u8, u8) {
+        let f = ((cell & 0b1111000000000000) >> 12) as u8;
+        let b = ((cell & 0b0000111100000000) >> 8) as u8;
+        let blink = cell & 0b0000000010000000 != 0;
+        if blink && !self.is_blink_visible(processor) {
+            (b, b)
+        } else {
+            (f, b)
+        }
+    }
+
+    /// Rasterizes the screen to RGB pixels, surrounded by `border_size` pixels of border
+    pub fn render_framebuffer(&self, processor: &Processor) -> Framebuffer {
+        let border = self.border_size;
+        let mut frame = Framebuffer::new(SCREEN_WIDTH + border * 2, SCREEN_HEIGHT + border * 2);
+        if !self.is_connected() {
+            return frame;
+        }
+
+        let border_color = color_to_rgb(self.get_color(processor, self.border_color as u8));
+        frame.fill_rect(0, 0, frame.width(), frame.height(), border_color);
+
         for y in 0..12 {
-            if y > 0 {
-                output += "\x1b[3B\n";
+            for x in 0..32 {
+                let cell = match self.get_cell(processor, x + y * 32) {
+                    Some(cell) => cell,
+                    None => continue,
+                };
+                let c = cell & 0b0000000001111111;
+                let (f, b) = self.get_cell_colors(processor, cell);
+                let fg = color_to_rgb(self.get_color(processor, f));
+                let bg = color_to_rgb(self.get_color(processor, b));
+                let word0 = self.get_font_word(processor, c * 2);
+                let word1 = self.get_font_word(processor, c * 2 + 1);
+                let columns = [word0 >> 8, word0 & 0xFF, word1 >> 8, word1 & 0xFF];
+
+                let left = border + x as usize * CHAR_WIDTH;
+                let top = border + y as usize * CHAR_HEIGHT;
+                for (col, bits) in columns.iter().enumerate() {
+                    for row in 0..CHAR_HEIGHT {
+                        let lit = bits & (1 << row) != 0;
+                        frame.set_pixel(left + col, top + row, if lit { fg } else { bg });
+                    }
+                }
             }
+        }
+
+        frame
+    }
+
+    pub fn render_ansi(&self, processor: &Processor) -> String {
+        let border = if self.is_connected() {
+            self.get_ansi_color(processor, self.border_color as u8)
+                .to_string()
+        } else {
+            "16".to_owned()
+        };
+        let mut output = self.render_ansi_border(&format!("\x1b[48;5;{}m", border));
+        let (columns, rows) = self.ansi_border_size();
+        for y in 0..12 {
+            output += &format!("\x1b[{};{}H", 1 + rows + y as usize * 4, 1 + columns);
             for x in 0..32 {
                 let i = x + y * 32;
-                let cell = processor.get_memory(self.screen_addr + i);
-                let c = (cell & 0b0000000001111111) as u16;
-                let f = ((cell & 0b1111000000000000) >> 12) as u8;
-                let b = ((cell & 0b0000111100000000) >> 8) as u8;
-                // FIXME support blinking
-                let x = ((cell & 0b0000000010000000) >> 7) as u8;
+                let cell = match self.get_cell(processor, i) {
+                    Some(cell) => cell,
+                    None => {
+                        output +=
+                            &format!("\x1b[38;5;16m\x1b[48;5;16m{}", self.get_wide_8x4_char(0));
+                        continue;
+                    }
+                };
+                let c = cell & 0b0000000001111111;
+                let (f, b) = self.get_cell_colors(processor, cell);
                 let fg = self.get_ansi_color(processor, f);
                 let bg = self.get_ansi_color(processor, b);
                 let tile = self.get_font_char(processor, c);
@@ -71,24 +248,33 @@ impl Monitor {
             }
         }
 
-        output
+        output + "\x1b[0m"
     }
 
     pub fn render_24bit_ansi(&self, processor: &Processor) -> String {
-        let mut output = "".to_owned();
-        output += "\x1b[0;0H";
+        let border = if self.is_connected() {
+            self.get_24bit_ansi_color(processor, self.border_color as u8)
+        } else {
+            "0;0;0".to_owned()
+        };
+        let mut output = self.render_ansi_border(&format!("\x1b[48;2;{}m", border));
+        let (columns, rows) = self.ansi_border_size();
         for y in 0..12 {
-            if y > 0 {
-                output += "\x1b[3B\n";
-            }
+            output += &format!("\x1b[{};{}H", 1 + rows + y as usize * 4, 1 + columns);
             for x in 0..32 {
                 let i = x + y * 32;
-                let cell = processor.get_memory(self.screen_addr + i);
-                let c = (cell & 0b0000000001111111) as u16;
-                let f = ((cell & 0b1111000000000000) >> 12) as u8;
-                let b = ((cell & 0b0000111100000000) >> 8) as u8;
-                // FIXME support blinking
-                let x = ((cell & 0b0000000010000000) >> 7) as u8;
+                let cell = match self.get_cell(processor, i) {
+                    Some(cell) => cell,
+                    None => {
+                        output += &format!(
+                            "\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m{}",
+                            self.get_wide_8x4_char(0)
+                        );
+                        continue;
+                    }
+                };
+                let c = cell & 0b0000000001111111;
+                let (f, b) = self.get_cell_colors(processor, cell);
                 let fg = self.get_24bit_ansi_color(processor, f);
                 let bg = self.get_24bit_ansi_color(processor, b);
                 let tile = self.get_font_char(processor, c);
@@ -96,29 +282,44 @@ impl Monitor {
             }
         }
 
+        output + "\x1b[0m"
+    }
+
+    /// Border thickness in terminal columns and rows for the ANSI renderers, where a column is
+    /// one pixel wide and a row is two pixels tall
+    pub fn ansi_border_size(&self) -> (usize, usize) {
+        (self.border_size, self.border_size.div_ceil(2))
+    }
+
+    /// Paints every border cell with `background`, so that none are left over from earlier frames
+    fn render_ansi_border(&self, background: &str) -> String {
+        let (columns, rows) = self.ansi_border_size();
+        let width = SCREEN_WIDTH + columns * 2;
+        let height = SCREEN_HEIGHT / 2 + rows * 2;
+        let mut output = background.to_owned();
+        for line in 1..=height {
+            if line <= rows || line > height - rows {
+                output += &format!("\x1b[{};1H{}", line, " ".repeat(width));
+            } else if columns > 0 {
+                let edge = " ".repeat(columns);
+                output += &format!("\x1b[{};1H{}", line, edge);
+                output += &format!("\x1b[{};{}H{}", line, width - columns + 1, edge);
+            }
+        }
         output
     }
 
     pub fn get_ansi_color(&self, processor: &Processor, index: u8) -> u16 {
-        let color = if self.palette_addr > 0 {
-            processor.get_memory(self.palette_addr + index as u16)
-        } else {
-            DEFAULT_PALETTE[index as usize]
-        };
+        let color = self.get_color(processor, index);
         let r = ((color & 0b0000111100000000) >> 8) / 3;
         let g = ((color & 0b0000000011110000) >> 4) / 3;
         let b = (color & 0b0000000000001111) / 3;
 
-        let ansi_color = 16 + 36 * r + 6 * g + b;
-        ansi_color
+        16 + 36 * r + 6 * g + b
     }
 
     pub fn get_24bit_ansi_color(&self, processor: &Processor, index: u8) -> String {
-        let color = if self.palette_addr > 0 {
-            processor.get_memory(self.palette_addr + index as u16)
-        } else {
-            DEFAULT_PALETTE[index as usize]
-        };
+        let color = self.get_color(processor, index);
         let r = ((color & 0b0000111100000000) >> 8) * 16;
         let g = ((color & 0b0000000011110000) >> 4) * 16;
         let b = (color & 0b0000000000001111) * 16;
@@ -128,16 +329,8 @@ impl Monitor {
 
     pub fn get_font_char(&self, processor: &Processor, index: u16) -> String {
         let addr = index * 2; // 2 words per char
-        let word0 = if self.font_addr > 0 {
-            processor.get_memory(self.font_addr + addr)
-        } else {
-            DEFAULT_FONT[addr as usize]
-        };
-        let word1 = if self.font_addr > 0 {
-            processor.get_memory(self.font_addr + addr + 1)
-        } else {
-            DEFAULT_FONT[addr as usize + 1]
-        };
+        let word0 = self.get_font_word(processor, addr);
+        let word1 = self.get_font_word(processor, addr + 1);
 
         let pixels = ((word0 as u32) << 16) + word1 as u32;
         self.get_wide_8x4_char(pixels)
@@ -147,13 +340,13 @@ impl Monitor {
         let col0 = ((pixels & 0xFF000000) >> 24) as u16;
         let col1 = ((pixels & 0x00FF0000) >> 16) as u16;
         let col2 = ((pixels & 0x0000FF00) >> 8) as u16;
-        let col3 = ((pixels & 0x000000FF) >> 0) as u16;
+        let col3 = (pixels & 0x000000FF) as u16;
 
         let block0 = (col0 & 0b00000011) + ((col1 & 0b00000011) << 2);
         let block1 = (col2 & 0b00000011) + ((col3 & 0b00000011) << 2);
 
-        let block2 = ((col0 & 0b00001100) >> 2) + ((col1 & 0b00001100) << 0);
-        let block3 = ((col2 & 0b00001100) >> 2) + ((col3 & 0b00001100) << 0);
+        let block2 = ((col0 & 0b00001100) >> 2) + (col1 & 0b00001100);
+        let block3 = ((col2 & 0b00001100) >> 2) + (col3 & 0b00001100);
 
         let block4 = ((col0 & 0b00110000) >> 4) + ((col1 & 0b00110000) >> 2);
         let block5 = ((col2 & 0b00110000) >> 4) + ((col3 & 0b00110000) >> 2);
@@ -178,13 +371,13 @@ impl Monitor {
         let col0 = ((pixels & 0xFF000000) >> 24) as u16;
         let col1 = ((pixels & 0x00FF0000) >> 16) as u16;
         let col2 = ((pixels & 0x0000FF00) >> 8) as u16;
-        let col3 = ((pixels & 0x000000FF) >> 0) as u16;
+        let col3 = (pixels & 0x000000FF) as u16;
 
         let block0 = (col0 & 0b00000011) + ((col1 & 0b00000011) << 2);
         let block1 = (col2 & 0b00000011) + ((col3 & 0b00000011) << 2);
 
-        let block2 = ((col0 & 0b00001100) >> 2) + ((col1 & 0b00001100) << 0);
-        let block3 = ((col2 & 0b00001100) >> 2) + ((col3 & 0b00001100) << 0);
+        let block2 = ((col0 & 0b00001100) >> 2) + (col1 & 0b00001100);
+        let block3 = ((col2 & 0b00001100) >> 2) + (col3 & 0b00001100);
 
         let block4 = ((col0 & 0b00110000) >> 4) + ((col1 & 0b00110000) >> 2);
         let block5 = ((col2 & 0b00110000) >> 4) + ((col3 & 0b00110000) >> 2);
@@ -292,16 +485,63 @@ impl HardwareDevice for Monitor {
     fn manufacturer(&self) -> u32 {
         0x1C6C8B36
     }
-    fn handle_interrupt(&mut self, processor: &Processor) {
+    fn handle_interrupt(&mut self, processor: &mut Processor) {
         let op = processor.get_register(A);
         let param = processor.get_register(B);
 
         match op {
-            0x00 => self.screen_addr = param,
+            0x00 => {
+                if !self.is_connected() && param != 0 {
+                    self.connected_at = Some(processor.cycle());
+                }
+                self.screen_addr = param;
+            }
             0x01 => self.font_addr = param,
             0x02 => self.palette_addr = param,
             0x03 => self.border_color = param,
+            0x04 => {
+                for (i, &word) in DEFAULT_FONT.iter().enumerate() {
+                    processor.set_memory(param.wrapping_add(i as u16), word);
+                }
+                processor.wait_cycles(MEM_DUMP_FONT_CYCLES);
+            }
+            0x05 => {
+                for (i, &word) in DEFAULT_PALETTE.iter().enumerate() {
+                    processor.set_memory(param.wrapping_add(i as u16), word);
+                }
+                processor.wait_cycles(MEM_DUMP_PALETTE_CYCLES);
+            }
             _ => {}
         }
     }
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_u16(self.screen_addr);
+        writer.write_u16(self.font_addr);
+        writer.write_u16(self.palette_addr);
+        writer.write_u16(self.border_color);
+        writer.write_bool(self.connected_at.is_some());
+        writer.write_u64(self.connected_at.unwrap_or(0) as u64);
+        writer.into_bytes()
+    }
+    fn load_state(&mut self, state: &[u8], version: u16) -> Result<(), SnapshotError> {
+        if version != 1 {
+            return Err(SnapshotError::InvalidDeviceState(self.id()));
+        }
+        let mut reader = StateReader::new(state);
+        let screen_addr = reader.read_u16()?;
+        let font_addr = reader.read_u16()?;
+        let palette_addr = reader.read_u16()?;
+        let border_color = reader.read_u16()?;
+        let is_connected = reader.read_bool()?;
+        let cycle = reader.read_u64()? as usize;
+        let connected_at = if is_connected { Some(cycle) } else { None };
+
+        self.screen_addr = screen_addr;
+        self.font_addr = font_addr;
+        self.palette_addr = palette_addr;
+        self.border_color = border_color;
+        self.connected_at = connected_at;
+        Ok(())
+    }
 }
@@ -39,3 +39,75 @@ pub const IAQ: OpCode = 0x000C;
 pub const HWN: OpCode = 0x0010;
 pub const HWQ: OpCode = 0x0011;
 pub const HWI: OpCode = 0x0012;
+
+pub const BASIC_OPCODES: [(&str, OpCode); 27] = [
+    ("SET", SET),
+    ("ADD", ADD),
+    ("SUB", SUB),
+    ("MUL", MUL),
+    ("MLI", MLI),
+    ("DIV", DIV),
+    ("DVI", DVI),
+    ("MOD", MOD),
+    ("MDI", MDI),
+    ("AND", AND),
+    ("BOR", BOR),
+    ("XOR", XOR),
+    ("SHR", SHR),
+    ("ASR", ASR),
+    ("SHL", SHL),
+    ("IFB", IFB),
+    ("IFC", IFC),
+    ("IFE", IFE),
+    ("IFN", IFN),
+    ("IFG", IFG),
+    ("IFA", IFA),
+    ("IFL", IFL),
+    ("IFU", IFU),
+    ("ADX", ADX),
+    ("SBX", SBX),
+    ("STI", STI),
+    ("STD", STD),
+];
+
+pub const SPECIAL_OPCODES: [(&str, OpCode); 9] = [
+    ("JSR", JSR),
+    ("INT", INT),
+    ("IAG", IAG),
+    ("IAS", IAS),
+    ("RFI", RFI),
+    ("IAQ", IAQ),
+    ("HWN", HWN),
+    ("HWQ", HWQ),
+    ("HWI", HWI),
+];
+
+/// Looks up a basic opcode by its (case insensitive) mnemonic
+pub fn basic_opcode(name: &str) -> Option<OpCode> {
+    BASIC_OPCODES
+        .iter()
+        .find(|(mnemonic, _)| mnemonic.eq_ignore_ascii_case(name))
+        .map(|&(_, op)| op)
+}
+
+/// Looks up a special opcode by its (case insensitive) mnemonic
+pub fn special_opcode(name: &str) -> Option<OpCode> {
+    SPECIAL_OPCODES
+        .iter()
+        .find(|(mnemonic, _)| mnemonic.eq_ignore_ascii_case(name))
+        .map(|&(_, op)| op)
+}
+
+pub fn basic_mnemonic(op: OpCode) -> Option<&'static str> {
+    BASIC_OPCODES
+        .iter()
+        .find(|&&(_, code)| code == op)
+        .map(|&(mnemonic, _)| mnemonic)
+}
+
+pub fn special_mnemonic(op: OpCode) -> Option<&'static str> {
+    SPECIAL_OPCODES
+        .iter()
+        .find(|&&(_, code)| code == op)
+        .map(|&(mnemonic, _)| mnemonic)
+}
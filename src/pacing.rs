@@ -0,0 +1,220 @@
+use super::clock::PROCESSOR_FREQUENCY;
+use super::processor::Processor;
+use super::watch::{RunSummary, StopReason};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Cycles run by each `Pacer::advance` when unthrottled
+pub const UNTHROTTLED_CHUNK: usize = 10_000;
+
+/// Host time for a `Pacer`. Swap in a fake to test pacing without waiting on real time.
+pub trait HostClock {
+    /// Monotonic time since some fixed point
+    fn now(&self) -> Duration;
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// The host's real monotonic clock
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl HostClock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// How fast emulated time passes compared to host time
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Speed {
+    /// A multiple of the clock rate, e.g. 0.5 for half speed
+    Multiplier(f64),
+    /// As fast as the host can go
+    Unthrottled,
+}
+
+/// Runs a processor in step with host time at its emulated clock rate
+pub struct Pacer<C: HostClock = SystemClock> {
+    clock: C,
+    frequency: usize,
+    speed: Speed,
+    frame_interval: Duration,
+    max_catch_up: Duration,
+    /// Host time up to which cycles have been handed out
+    last: Option<Duration>,
+    /// Cycles that are due but haven't been run yet
+    credit: f64,
+    dropped: usize,
+}
+
+impl Default for Pacer<SystemClock> {
+    fn default() -> Pacer<SystemClock> {
+        Pacer::new()
+    }
+}
+
+impl Pacer<SystemClock> {
+    pub fn new() -> Pacer<SystemClock> {
+        Pacer::with_clock(SystemClock::new())
+    }
+}
+
+impl<C: HostClock> Pacer<C> {
+    /// Runs at `PROCESSOR_FREQUENCY` and full speed, with 60 frames per second and up to
+    /// 100ms of catching up
+    pub fn with_clock(clock: C) -> Pacer<C> {
+        Pacer {
+            clock,
+            frequency: PROCESSOR_FREQUENCY,
+            speed: Speed::Multiplier(1.0),
+            frame_interval: Duration::from_micros(16_667),
+            max_catch_up: Duration::from_millis(100),
+            last: None,
+            credit: 0.0,
+            dropped: 0,
+        }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    pub fn clock_mut(&mut self) -> &mut C {
+        &mut self.clock
+    }
+
+    /// Emulated cycles per second at normal speed
+    pub fn frequency(&self) -> usize {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: usize) {
+        self.frequency = frequency;
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+    }
+
+    /// Host time between calls to the frame callback in `run`
+    pub fn frame_interval(&self) -> Duration {
+        self.frame_interval
+    }
+
+    pub fn set_frame_interval(&mut self, interval: Duration) {
+        self.frame_interval = interval;
+    }
+
+    /// How far behind host time the processor is allowed to fall. Anything beyond that, e.g.
+    /// after the host was suspended, is dropped rather than run in one long burst.
+    pub fn max_catch_up(&self) -> Duration {
+        self.max_catch_up
+    }
+
+    pub fn set_max_catch_up(&mut self, max_catch_up: Duration) {
+        self.max_catch_up = max_catch_up;
+    }
+
+    /// Total cycles skipped because the processor fell too far behind
+    pub fn dropped_cycles(&self) -> usize {
+        self.dropped
+    }
+
+    /// Forgets any time that has passed, e.g. after the processor was paused
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.credit = 0.0;
+    }
+
+    /// Runs the cycles that have come due since the last call, returning what was run. The first
+    /// call after creating or resetting the pacer only starts the clock.
+    pub fn advance(&mut self, processor: &mut Processor) -> RunSummary {
+        let now = self.clock.now();
+        let multiplier = match self.speed {
+            Speed::Multiplier(multiplier) => multiplier,
+            Speed::Unthrottled => {
+                self.last = Some(now);
+                return processor.run_cycles(UNTHROTTLED_CHUNK);
+            }
+        };
+
+        let elapsed = match self.last {
+            Some(last) => now.checked_sub(last).unwrap_or_default(),
+            None => Duration::from_secs(0),
+        };
+        self.last = Some(now);
+
+        let cycles_per_second = self.frequency as f64 * multiplier;
+        self.credit += elapsed.as_secs_f64() * cycles_per_second;
+        let max_credit = self.max_catch_up.as_secs_f64() * cycles_per_second;
+        if self.credit > max_credit {
+            self.dropped += (self.credit - max_credit) as usize;
+            self.credit = max_credit;
+        }
+
+        let summary = processor.run_cycles(self.credit as usize);
+        self.credit -= summary.cycles as f64;
+        if summary.stop != StopReason::CycleLimit {
+            // Whatever stopped it will need dealing with before carrying on
+            self.credit = 0.0;
+        }
+
+        summary
+    }
+
+    /// Keeps the processor running in real time, calling `on_frame` once every frame interval
+    /// until it returns false, or until the processor stops at a breakpoint, watchpoint or error.
+    /// Returns why the processor stopped, or `None` if `on_frame` ended it.
+    pub fn run<F>(&mut self, processor: &mut Processor, mut on_frame: F) -> Option<StopReason>
+    where
+        F: FnMut(&mut Processor) -> bool,
+    {
+        self.reset();
+        self.advance(processor);
+        let mut next_frame = self.clock.now() + self.frame_interval;
+
+        loop {
+            let summary = self.advance(processor);
+            if summary.stop != StopReason::CycleLimit {
+                return Some(summary.stop);
+            }
+
+            let now = self.clock.now();
+            if now >= next_frame {
+                if !on_frame(processor) {
+                    return None;
+                }
+                next_frame += self.frame_interval;
+                if next_frame <= now {
+                    // Too far behind to keep up, so skip the missed frames
+                    next_frame = now + self.frame_interval;
+                }
+            } else if self.speed != Speed::Unthrottled {
+                self.clock.sleep(next_frame - now);
+            }
+        }
+    }
+}
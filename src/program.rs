@@ -2,7 +2,13 @@ use super::instruction::Instruction;
 use super::opcodes::OpCode;
 use super::value::Value;
 
+#[derive(Clone, Debug)]
 pub struct Program(Vec<u16>);
+impl Default for Program {
+    fn default() -> Program {
+        Program::new()
+    }
+}
 impl Program {
     pub fn new() -> Program {
         Program(Vec::with_capacity(64))
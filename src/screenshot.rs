@@ -0,0 +1,286 @@
+use super::framebuffer::Framebuffer;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Set this environment variable to have `assert_matches_golden` write new golden images
+pub const UPDATE_GOLDEN_VAR: &str = "DCPU_UPDATE_GOLDEN";
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Most bytes a stored deflate block can hold
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    /// The file isn't a binary (P6) PPM image with 8 bit channels
+    InvalidPpm(&'static str),
+    Io(io::Error),
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScreenshotError::InvalidPpm(reason) => write!(f, "invalid PPM image: {}", reason),
+            ScreenshotError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ScreenshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ScreenshotError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ScreenshotError {
+    fn from(err: io::Error) -> ScreenshotError {
+        ScreenshotError::Io(err)
+    }
+}
+
+impl Framebuffer {
+    /// Writes a binary (P6) PPM image
+    pub fn write_ppm<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width(), self.height())?;
+        writer.write_all(self.pixels())
+    }
+
+    /// Reads a binary (P6) PPM image with 8 bit channels
+    pub fn read_ppm<R: Read>(reader: &mut R) -> Result<Framebuffer, ScreenshotError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        let mut pos = 0;
+        if ppm_token(&bytes, &mut pos) != Some(b"P6".as_ref()) {
+            return Err(ScreenshotError::InvalidPpm("not a binary PPM"));
+        }
+        let width = ppm_number(&bytes, &mut pos)?;
+        let height = ppm_number(&bytes, &mut pos)?;
+        if ppm_number(&bytes, &mut pos)? != 255 {
+            return Err(ScreenshotError::InvalidPpm("channels aren't 8 bit"));
+        }
+        // A single whitespace byte separates the header from the pixels
+        let pixels = bytes.get(pos + 1..).unwrap_or_default();
+
+        Framebuffer::from_pixels(width, height, pixels.to_vec())
+            .ok_or(ScreenshotError::InvalidPpm("wrong number of pixels"))
+    }
+
+    /// Writes a truecolor PNG image. The pixels are stored uncompressed so that no deflate
+    /// implementation is needed.
+    pub fn write_png<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut header = vec![];
+        header.extend_from_slice(&(self.width() as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height() as u32).to_be_bytes());
+        // 8 bit RGB, default compression and filtering, not interlaced
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        // Each scanline starts with its filter type, which is always none
+        let stride = self.width() * 3;
+        let mut scanlines = Vec::with_capacity((stride + 1) * self.height());
+        for row in self.pixels().chunks(stride.max(1)) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+
+        writer.write_all(&PNG_SIGNATURE)?;
+        write_png_chunk(writer, b"IHDR", &header)?;
+        write_png_chunk(writer, b"IDAT", &zlib_stored(&scanlines))?;
+        write_png_chunk(writer, b"IEND", &[])
+    }
+
+    /// Saves as a PNG if `path` ends in `.png`, and as a PPM otherwise
+    pub fn save_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ScreenshotError> {
+        let path = path.as_ref();
+        let mut writer = BufWriter::new(File::create(path)?);
+        if path.extension().is_some_and(|ext| ext == "png") {
+            self.write_png(&mut writer)?;
+        } else {
+            self.write_ppm(&mut writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load_ppm_file<P: AsRef<Path>>(path: P) -> Result<Framebuffer, ScreenshotError> {
+        Framebuffer::read_ppm(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+/// Skips whitespace and `#` comments, then returns the next whitespace separated token
+fn ppm_token<'a>(bytes: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    loop {
+        match bytes.get(*pos)? {
+            b'#' => {
+                while bytes.get(*pos).is_some_and(|&b| b != b'\n') {
+                    *pos += 1;
+                }
+            }
+            b if b.is_ascii_whitespace() => *pos += 1,
+            _ => break,
+        }
+    }
+
+    let start = *pos;
+    while bytes.get(*pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    Some(&bytes[start..*pos])
+}
+
+fn ppm_number(bytes: &[u8], pos: &mut usize) -> Result<usize, ScreenshotError> {
+    ppm_token(bytes, pos)
+        .and_then(|token| std::str::from_utf8(token).ok())
+        .and_then(|token| token.parse().ok())
+        .ok_or(ScreenshotError::InvalidPpm("bad header"))
+}
+
+fn write_png_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(&[kind.as_ref(), data]);
+    writer.write_all(&crc.to_be_bytes())
+}
+
+/// Wraps `data` in a zlib stream made of stored (uncompressed) deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len() / MAX_STORED_BLOCK + 1;
+    let mut output = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    // Deflate with a 32K window, no preset dictionary, fastest compression
+    output.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        output.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let is_last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        output.push(is_last as u8);
+        output.extend_from_slice(&len.to_le_bytes());
+        output.extend_from_slice(&(!len).to_le_bytes());
+        output.extend_from_slice(chunk);
+    }
+
+    output.extend_from_slice(&adler32(data).to_be_bytes());
+    output
+}
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in parts.iter().flat_map(|part| part.iter()) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Highlights the pixels that differ between two frames of the same size in red, over a dimmed
+/// copy of `expected`. Returns None if they're the same.
+pub fn diff_frames(expected: &Framebuffer, actual: &Framebuffer) -> Option<Framebuffer> {
+    if expected == actual {
+        return None;
+    }
+
+    let mut diff = Framebuffer::new(expected.width(), expected.height());
+    for y in 0..expected.height() {
+        for x in 0..expected.width() {
+            let pixel = expected.get_pixel(x, y);
+            if pixel == actual.get_pixel(x, y) {
+                let [r, g, b] = pixel;
+                let gray = ((r as u16 + g as u16 + b as u16) / 12) as u8;
+                diff.set_pixel(x, y, [gray, gray, gray]);
+            } else {
+                diff.set_pixel(x, y, [255, 0, 0]);
+            }
+        }
+    }
+    Some(diff)
+}
+
+/// `path` with its extension replaced by `suffix`
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}{}", stem, suffix))
+}
+
+/// Panics unless `frame` is identical to the PPM golden image at `path`.
+///
+/// On a mismatch, `<name>.actual.png` and `<name>.diff.png` are written next to the golden image
+/// to show what went wrong. If the `DCPU_UPDATE_GOLDEN` environment variable is set, the golden
+/// image is written from `frame` instead.
+pub fn assert_matches_golden<P: AsRef<Path>>(frame: &Framebuffer, path: P) {
+    let path = path.as_ref();
+    if env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).expect("failed to create golden image directory");
+        }
+        frame.save_file(path).expect("failed to write golden image");
+        return;
+    }
+
+    let golden = Framebuffer::load_ppm_file(path).unwrap_or_else(|err| {
+        panic!(
+            "failed to load golden image {}: {}. Set {} to create it.",
+            path.display(),
+            err,
+            UPDATE_GOLDEN_VAR
+        )
+    });
+
+    let actual_path = sibling_path(path, ".actual.png");
+    if (golden.width(), golden.height()) != (frame.width(), frame.height()) {
+        frame
+            .save_file(&actual_path)
+            .expect("failed to write actual image");
+        panic!(
+            "frame is {}x{} but golden image {} is {}x{}, see {}",
+            frame.width(),
+            frame.height(),
+            path.display(),
+            golden.width(),
+            golden.height(),
+            actual_path.display()
+        );
+    }
+
+    if let Some(diff) = diff_frames(&golden, frame) {
+        let diff_path = sibling_path(path, ".diff.png");
+        frame
+            .save_file(&actual_path)
+            .expect("failed to write actual image");
+        diff.save_file(&diff_path)
+            .expect("failed to write diff image");
+        let differing = (0..frame.height())
+            .flat_map(|y| (0..frame.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| frame.get_pixel(x, y) != golden.get_pixel(x, y))
+            .count();
+        panic!(
+            "{} pixels differ from golden image {}, see {} and {}",
+            differing,
+            path.display(),
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Every snapshot starts with these bytes
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"DCSS";
+
+/// Bumped whenever the snapshot layout changes
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+pub(crate) const MEMORY_WORDS: usize = 0x10000;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The data doesn't start with `SNAPSHOT_MAGIC`
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// The data ended before the snapshot was complete
+    Truncated,
+    /// The snapshot's memory has this many words instead of 0x10000
+    MemorySize(usize),
+    /// The hardware attached to the processor doesn't match the devices in the snapshot
+    HardwareMismatch(usize),
+    /// The hardware device at this index is already in use
+    DeviceBusy(usize),
+    /// A device couldn't make sense of its saved state
+    InvalidDeviceState(u32),
+    Io(io::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::MemorySize(len) => {
+                write!(f, "snapshot has {} words of memory instead of 65536", len)
+            }
+            SnapshotError::HardwareMismatch(index) => {
+                write!(f, "hardware device {} doesn't match the snapshot", index)
+            }
+            SnapshotError::DeviceBusy(index) => write!(f, "hardware device {} is busy", index),
+            SnapshotError::InvalidDeviceState(id) => {
+                write!(f, "invalid state for hardware device {:#010x}", id)
+            }
+            SnapshotError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SnapshotError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> SnapshotError {
+        SnapshotError::Io(err)
+    }
+}
+
+/// Little-endian encoder for snapshot data, also used by devices to save their state
+#[derive(Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { bytes: vec![] }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a length-prefixed list of words
+    pub fn write_words(&mut self, words: &[u16]) {
+        self.write_u32(words.len() as u32);
+        for &word in words {
+            self.write_u16(word);
+        }
+    }
+
+    /// Writes a length-prefixed list of bytes
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Decoder for data written by a `StateWriter`
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SnapshotError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SnapshotError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_words(&mut self) -> Result<Vec<u16>, SnapshotError> {
+        let len = self.read_u32()? as usize;
+        if self.bytes.len() < len * 2 {
+            return Err(SnapshotError::Truncated);
+        }
+        (0..len).map(|_| self.read_u16()).collect()
+    }
+
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, SnapshotError> {
+        let len = self.read_u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Number of bytes that haven't been read yet
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+/// Saved state of one attached hardware device
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceState {
+    pub id: u32,
+    pub version: u16,
+    /// `HardwareDevice::state_version` when the state was saved
+    pub state_version: u16,
+    /// Whatever the device returned from `HardwareDevice::save_state`
+    pub data: Vec<u8>,
+}
+
+/// The complete state of a `Processor` and its hardware at one point in time.
+/// Take one with `Processor::snapshot` and put it back with `Processor::restore`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// All 0x10000 words of memory
+    pub memory: Vec<u16>,
+    pub registers: [u16; 12],
+    pub cycle_wait: u16,
+    pub cycle: u64,
+    pub is_queuing_interrupts: bool,
+    pub is_on_fire: bool,
+    pub interrupt_queue: Vec<u16>,
+    /// One entry per attached device, in the order they were connected
+    pub devices: Vec<DeviceState>,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        for &byte in &SNAPSHOT_MAGIC {
+            writer.write_u8(byte);
+        }
+        writer.write_u16(SNAPSHOT_VERSION);
+        for &register in &self.registers {
+            writer.write_u16(register);
+        }
+        writer.write_u16(self.cycle_wait);
+        writer.write_u64(self.cycle);
+        writer.write_bool(self.is_queuing_interrupts);
+        writer.write_bool(self.is_on_fire);
+        writer.write_words(&self.interrupt_queue);
+        writer.write_words(&self.memory);
+        writer.write_u32(self.devices.len() as u32);
+        for device in &self.devices {
+            writer.write_u32(device.id);
+            writer.write_u16(device.version);
+            writer.write_u16(device.state_version);
+            writer.write_bytes(&device.data);
+        }
+
+        writer.into_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let mut reader = StateReader::new(bytes);
+        for &byte in &SNAPSHOT_MAGIC {
+            if reader.read_u8().map_err(|_| SnapshotError::BadMagic)? != byte {
+                return Err(SnapshotError::BadMagic);
+            }
+        }
+        let version = reader.read_u16()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let mut registers = [0; 12];
+        for register in registers.iter_mut() {
+            *register = reader.read_u16()?;
+        }
+        let cycle_wait = reader.read_u16()?;
+        let cycle = reader.read_u64()?;
+        let is_queuing_interrupts = reader.read_bool()?;
+        let is_on_fire = reader.read_bool()?;
+        let interrupt_queue = reader.read_words()?;
+        let memory = reader.read_words()?;
+        if memory.len() != MEMORY_WORDS {
+            return Err(SnapshotError::MemorySize(memory.len()));
+        }
+
+        let count = reader.read_u32()?;
+        let mut devices = vec![];
+        for _ in 0..count {
+            let id = reader.read_u32()?;
+            let device_version = reader.read_u16()?;
+            let state_version = reader.read_u16()?;
+            devices.push(DeviceState {
+                id,
+                version: device_version,
+                state_version,
+                data: reader.read_bytes()?,
+            });
+        }
+
+        Ok(Snapshot {
+            memory,
+            registers,
+            cycle_wait,
+            cycle,
+            is_queuing_interrupts,
+            is_on_fire,
+            interrupt_queue,
+            devices,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), SnapshotError> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Snapshot, SnapshotError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        Snapshot::from_bytes(&bytes)
+    }
+
+    pub fn save_file<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        let mut file = File::create(path)?;
+        self.write_to(&mut file)
+    }
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+        let mut file = File::open(path)?;
+        Snapshot::read_from(&mut file)
+    }
+}
@@ -1,10 +1,9 @@
 use super::*;
 use super::Register::*;
 use super::opcodes::*;
-use std::mem;
 
 fn to_unsigned(val: i16) -> u16 {
-    unsafe { mem::transmute(val) }
+    val as u16
 }
 
 #[test]
@@ -455,6 +454,54 @@ fn shl_register_with_literal() {
     assert_eq!(machine.cycle_wait, 0);
 }
 
+/// Runs `op A, B` and returns A and EX
+fn shift(op: OpCode, b: u16, a: u16) -> (u16, u16) {
+    let mut machine = Processor::new();
+    let words = Instruction::new(op, Value::Register(A), Value::Register(B)).words();
+    for (i, &word) in words.iter().enumerate() {
+        machine.memory[i as u16] = word;
+    }
+    machine.set_register(A, b);
+    machine.set_register(B, a);
+    machine.tick();
+    (machine.get_register(A), machine.get_register(EX))
+}
+
+#[test]
+fn shr_by_16_or_more() {
+    assert_eq!(shift(SHR, 0x8001, 1), (0x4000, 0x8000));
+    assert_eq!(shift(SHR, 0x8001, 15), (0x0001, 0x0002));
+    assert_eq!(shift(SHR, 0x8001, 16), (0x0000, 0x8001));
+    assert_eq!(shift(SHR, 0x8001, 17), (0x0000, 0x4000));
+    assert_eq!(shift(SHR, 0x8001, 31), (0x0000, 0x0001));
+    assert_eq!(shift(SHR, 0x8001, 32), (0x0000, 0x0000));
+    assert_eq!(shift(SHR, 0xffff, 0xffff), (0x0000, 0x0000));
+}
+
+#[test]
+fn asr_negative_and_by_16_or_more() {
+    assert_eq!(shift(ASR, 0x8001, 1), (0xc000, 0x8000));
+    assert_eq!(shift(ASR, 0x8001, 15), (0xffff, 0x0002));
+    assert_eq!(shift(ASR, 0x8001, 16), (0xffff, 0x8001));
+    assert_eq!(shift(ASR, 0x8001, 17), (0xffff, 0xc000));
+    assert_eq!(shift(ASR, 0x8001, 32), (0xffff, 0xffff));
+    assert_eq!(shift(ASR, 0x8001, 0xffff), (0xffff, 0xffff));
+    // Positive values shift in zeros
+    assert_eq!(shift(ASR, 0x4001, 1), (0x2000, 0x8000));
+    assert_eq!(shift(ASR, 0x4001, 16), (0x0000, 0x4001));
+    assert_eq!(shift(ASR, 0x4001, 32), (0x0000, 0x0000));
+}
+
+#[test]
+fn shl_by_16_or_more() {
+    assert_eq!(shift(SHL, 0x8001, 1), (0x0002, 0x0001));
+    assert_eq!(shift(SHL, 0x8001, 15), (0x8000, 0x4000));
+    assert_eq!(shift(SHL, 0x8001, 16), (0x0000, 0x8001));
+    assert_eq!(shift(SHL, 0x8001, 17), (0x0000, 0x0002));
+    assert_eq!(shift(SHL, 0x8001, 32), (0x0000, 0x0000));
+    assert_eq!(shift(SHL, 0xffff, 0xffff), (0x0000, 0x0000));
+}
+
 // Conditionals
 #[test]
 fn ifb_register_with_literal_when_true() {
@@ -1415,3 +1462,2645 @@ fn copy_from_memory_to_memory_using_registers() {
     assert_eq!(machine.get_memory(0xBEEF), 0x5555);
     assert_eq!(machine.get_register(PC), 0x0005);
 }
+
+// Assembler
+#[test]
+fn assemble_basic_instructions() {
+    let program = assemble("SET A, 0x30\nSET [0x1000], 0x20\nSUB A, [0x1000]\n").unwrap();
+    assert_eq!(
+        program.words(),
+        &vec![0x7c01, 0x0030, 0x7fc1, 0x0020, 0x1000, 0x7803, 0x1000]
+    );
+}
+
+#[test]
+fn assemble_short_literals() {
+    let program = assemble("SET A, 0\nSET A, 30\nSET A, -1\nSET A, 31\n").unwrap();
+    assert_eq!(
+        program.words(),
+        &vec![0x8401, 0xfc01, 0x8001, 0x7c01, 0x001f]
+    );
+}
+
+#[test]
+fn assemble_labels() {
+    let source = "
+        SET I, 10           ; Loop counter
+        :loop SET [0x2000+I], [A]
+        SUB I, 1
+        IFN I, 0
+            SET PC, loop
+        JSR end
+        :end SET PC, end
+    ";
+    let mut assembler = Assembler::new();
+    let program = assembler.assemble(source).unwrap();
+    assert_eq!(assembler.label("loop"), Some(0x0001));
+    assert_eq!(assembler.label("end"), Some(0x0009));
+    assert_eq!(
+        program.words(),
+        &vec![
+            0xacc1, 0x22c1, 0x2000, 0x88c3, 0x84d3, 0x7f81, 0x0001, 0x7c20, 0x0009, 0x7f81, 0x0009
+        ]
+    );
+}
+
+#[test]
+fn assemble_stack_operands() {
+    let program = assemble(
+        "SET PUSH, A\nSET B, POP\nSET C, PEEK\nSET X, PICK 3\nSET [--SP], [SP++]\nSET Y, [SP+2]",
+    )
+    .unwrap();
+    assert_eq!(
+        program.words(),
+        &vec![0x0301, 0x6021, 0x6441, 0x6861, 0x0003, 0x6301, 0x6881, 0x0002]
+    );
+}
+
+#[test]
+fn assemble_data() {
+    let program = assemble(":text DAT \"hi\", 0x10, text\nDAT\n1, 2,\n3\nSET A, text").unwrap();
+    assert_eq!(
+        program.words(),
+        &vec![0x0068, 0x0069, 0x0010, 0x0000, 0x0001, 0x0002, 0x0003, 0x7c01, 0x0000]
+    );
+}
+
+#[test]
+fn assemble_special_instructions() {
+    let program = assemble("HWN Z\nHWQ Z\nHWI [0x1234]\nIAS 0\nRFI 0").unwrap();
+    assert_eq!(
+        program.words(),
+        &vec![0x1600, 0x1620, 0x7a40, 0x1234, 0x8540, 0x8560]
+    );
+}
+
+#[test]
+fn assemble_nyan() {
+    let source = include_str!("../progs/nyan.dasm");
+    let binary = include_bytes!("../progs/nyan.bin");
+    let program = assemble(source).unwrap();
+    let words: Vec<u16> = binary
+        .chunks(2)
+        .map(|pair| (pair[0] as u16) << 8 | pair[1] as u16)
+        .collect();
+    assert_eq!(program.words(), &words);
+}
+
+#[test]
+fn assemble_errors_report_position() {
+    let error = assemble("SET A, 1\n  FOO A, 1").unwrap_err();
+    assert_eq!(error.line, 2);
+    assert_eq!(error.column, 3);
+    assert_eq!(
+        error.kind,
+        AssemblerErrorKind::UnknownInstruction("FOO".to_owned())
+    );
+
+    let error = assemble("SET A, missing").unwrap_err();
+    assert_eq!((error.line, error.column), (1, 8));
+    assert_eq!(
+        error.kind,
+        AssemblerErrorKind::UnknownLabel("missing".to_owned())
+    );
+
+    let error = assemble(":a SET A, 1\n:a SET A, 2").unwrap_err();
+    assert_eq!(
+        error.kind,
+        AssemblerErrorKind::DuplicateLabel("a".to_owned())
+    );
+
+    let error = assemble("SET POP, A").unwrap_err();
+    assert_eq!((error.line, error.column), (1, 5));
+
+    let error = assemble("SET A B").unwrap_err();
+    assert_eq!(
+        error.kind,
+        AssemblerErrorKind::UnexpectedToken("B".to_owned())
+    );
+
+    let error = assemble("SET A, 0x10000").unwrap_err();
+    assert_eq!(
+        error.kind,
+        AssemblerErrorKind::InvalidNumber("0x10000".to_owned())
+    );
+
+    let error = assemble("DAT \"oops").unwrap_err();
+    assert_eq!(error.kind, AssemblerErrorKind::UnterminatedString);
+}
+
+#[test]
+fn continued_data_must_fit_in_memory() {
+    let fits = format!("DAT\n{}0\n", "0, ".repeat(0xFFFF));
+    assert_eq!(assemble(&fits).unwrap().words().len(), 0x10000);
+
+    let error = assemble(&format!("DAT\n{}0, 0\n", "0, ".repeat(0xFFFF))).unwrap_err();
+    assert_eq!(error.line, 2);
+    assert_eq!(error.kind, AssemblerErrorKind::ProgramTooLarge);
+}
+
+#[test]
+fn parse_literals_and_register_names() {
+    assert_eq!(parse_literal("42"), Some(42));
+    assert_eq!(parse_literal("0x1F"), Some(0x1f));
+    assert_eq!(parse_literal("0X1f"), Some(0x1f));
+    assert_eq!(parse_literal("0b101"), Some(5));
+    assert_eq!(parse_literal("0x10000"), None);
+    assert_eq!(parse_literal("loop"), None);
+
+    assert_eq!(Register::from_name("pc"), Some(PC));
+    assert_eq!(Register::from_name("Ia"), Some(IA));
+    assert_eq!(Register::from_name("loop"), None);
+    // IA isn't a value the assembler can encode, so it's just a label there
+    assert_eq!(
+        assemble("SET A, IA").unwrap_err().kind,
+        AssemblerErrorKind::UnknownLabel("IA".to_owned())
+    );
+}
+
+// Disassembler
+#[test]
+fn disassemble_words() {
+    let words = [
+        0x7c01, 0x0030, 0x7fc1, 0x0020, 0x1000, 0x7803, 0x1000, 0x6a01, 0x0003, 0x0010,
+    ];
+    let lines: Vec<String> = disassemble(&words, 0x0000)
+        .iter()
+        .map(|line| line.text.clone())
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            "SET A, 0x30",
+            "SET [0x1000], 0x20",
+            "SUB A, [0x1000]",
+            "SET [A+0x10], PICK 3",
+        ]
+    );
+}
+
+#[test]
+fn disassemble_tracks_addresses() {
+    let program = assemble("SET PUSH, 5\nJSR 0x1234\nSET PC, POP\nIFE [B], -1").unwrap();
+    let lines = disassemble(program.words(), 0x0100);
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0].addr, 0x0100);
+    assert_eq!(lines[0].text, "SET PUSH, 5");
+    assert_eq!(lines[1].addr, 0x0101);
+    assert_eq!(lines[1].words, vec![0x7c20, 0x1234]);
+    assert_eq!(lines[1].text, "JSR 0x1234");
+    assert_eq!(lines[2].addr, 0x0103);
+    assert_eq!(lines[2].text, "SET PC, POP");
+    assert_eq!(lines[3].text, "IFE [B], 0xffff");
+    assert_eq!(format!("{}", lines[1]), "0101: 7c20 1234      JSR 0x1234");
+}
+
+#[test]
+fn disassemble_invalid_opcodes_as_data() {
+    // 0x18 is an unused basic opcode, 0x1f special is unused, and the last
+    // instruction is missing its next word
+    let lines = disassemble(&[0x0018, 0x03e0, 0x7c01], 0x0000);
+    let text: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+    assert_eq!(text, vec!["DAT 0x0018", "DAT 0x03e0", "DAT 0x7c01"]);
+}
+
+#[test]
+fn disassemble_processor_memory() {
+    let mut machine = Processor::new();
+    let program = assemble("SET A, 1\nADD A, [0x8000]\nSUB A, 1").unwrap();
+    machine.memory.load_program(0x0000, &program);
+    let lines = machine.disassemble(0x0001, 2);
+    assert_eq!(lines[0].text, "ADD A, [0x8000]");
+    assert_eq!(lines[1].addr, 0x0003);
+    assert_eq!(lines[1].text, "SUB A, 1");
+}
+
+// Clock
+#[test]
+fn clock_ticks_against_emulated_cycles() {
+    let mut machine = Processor::new();
+    machine.connect_hardware(Clock::new());
+    // Tick 60 times a second
+    let program = assemble("SET A, 0\nSET B, 1\nHWI 0\n:loop SET PC, loop").unwrap();
+    machine.memory.load_program(0x0000, &program);
+
+    for _ in 0..PROCESSOR_FREQUENCY {
+        machine.tick();
+    }
+
+    let cycle = machine.cycle();
+    machine.with_hardware_mut(0, |clock: &mut Clock, _| {
+        assert!(clock.is_running());
+        // Devices are ticked along with the processor, so the clock is already up to date
+        assert_eq!(clock.update(cycle), 0);
+        assert_eq!(clock.ticks(), 59);
+        assert_eq!(clock.update(cycle + PROCESSOR_FREQUENCY / 60), 1);
+        assert_eq!(clock.ticks(), 60);
+    });
+}
+
+#[test]
+fn clock_interrupt_message() {
+    let mut machine = Processor::new();
+    machine.connect_hardware(Clock::new());
+    let program =
+        assemble("SET A, 2\nSET B, 0x1234\nHWI 0\nSET A, 0\nSET B, 6\nHWI 0\n:loop SET PC, loop")
+            .unwrap();
+    machine.memory.load_program(0x0000, &program);
+
+    for _ in 0..20 {
+        machine.tick();
+    }
+
+    machine.with_hardware_mut(0, |clock: &mut Clock, _| {
+        assert_eq!(clock.interrupt_message(), Some(0x1234));
+        assert_eq!(clock.interval, 6);
+        assert_eq!(clock.update(20 + PROCESSOR_FREQUENCY), 10);
+        clock.start(0, 0);
+        assert!(!clock.is_running());
+        assert_eq!(clock.update(20 + PROCESSOR_FREQUENCY * 2), 0);
+    });
+}
+
+#[test]
+fn clock_restarts_and_stops() {
+    let mut machine = Processor::new();
+    machine.connect_hardware(Clock::new());
+    let mut assembler = Assembler::new();
+    let program = assembler
+        .assemble(
+            "
+        SET A, 0
+        SET B, 60
+        HWI 0           ; Tick once a second
+        :wait SET PC, wait ; Spin until the test moves PC
+        :restart
+        HWI 0           ; Restarting resets the tick count
+        SET A, 1
+        HWI 0
+        SET X, C
+        SET A, 0
+        SET B, 0
+        HWI 0           ; Turn the clock off
+        SET A, 1
+        :loop HWI 0
+        SET PC, loop
+        ",
+        )
+        .unwrap();
+    machine.memory.load_program(0x0000, &program);
+
+    for _ in 0..PROCESSOR_FREQUENCY + 100 {
+        machine.tick();
+    }
+    machine.with_hardware_mut(0, |clock: &mut Clock, _| assert_eq!(clock.ticks(), 1));
+
+    machine.set_register(PC, assembler.label("restart").unwrap());
+    for _ in 0..PROCESSOR_FREQUENCY * 2 {
+        machine.tick();
+    }
+    assert_eq!(machine.get_register(X), 0);
+    assert_eq!(machine.get_register(C), 0);
+    machine.with_hardware_mut(0, |clock: &mut Clock, _| assert!(!clock.is_running()));
+}
+
+// Keyboard
+#[test]
+fn keyboard_buffers_typed_keys() {
+    let mut keyboard = Keyboard::new();
+    keyboard.type_str("Hi\n");
+    keyboard.type_key(keyboard::KEY_UP);
+    keyboard.type_key(0x05); // Not a key
+    assert_eq!(keyboard.buffer_len(), 4);
+    assert_eq!(keyboard.next_key(), 'H' as u16);
+    assert_eq!(keyboard.next_key(), 'i' as u16);
+    assert_eq!(keyboard.next_key(), keyboard::KEY_RETURN);
+    assert_eq!(keyboard.next_key(), keyboard::KEY_UP);
+    assert_eq!(keyboard.next_key(), 0);
+}
+
+#[test]
+fn keyboard_tracks_pressed_keys() {
+    let mut keyboard = Keyboard::new();
+    keyboard.press_key(keyboard::KEY_SHIFT);
+    keyboard.press_key('a' as u16);
+    keyboard.release_key('a' as u16);
+    assert!(keyboard.is_pressed(keyboard::KEY_SHIFT));
+    assert!(!keyboard.is_pressed('a' as u16));
+    assert!(!keyboard.is_pressed(0xFFFF));
+}
+
+#[test]
+fn keyboard_interrupts() {
+    let mut machine = Processor::new();
+    machine.connect_hardware(Keyboard::new());
+    let program = assemble("SET A, 3\nSET B, 0x55\nHWI 0\n:loop SET PC, loop").unwrap();
+    machine.memory.load_program(0x0000, &program);
+    for _ in 0..10 {
+        machine.tick();
+    }
+
+    machine.with_hardware_mut(0, |keyboard: &mut Keyboard, _| {
+        assert_eq!(keyboard.take_interrupt(), None);
+        keyboard.type_key('x' as u16);
+        assert_eq!(keyboard.take_interrupt(), Some(0x55));
+        assert_eq!(keyboard.take_interrupt(), None);
+        keyboard.release_key(keyboard::KEY_CONTROL);
+        assert_eq!(keyboard.take_interrupt(), Some(0x55));
+    });
+}
+
+// Hardware
+struct TestDevice;
+impl HardwareDevice for TestDevice {
+    fn id(&self) -> u32 {
+        0x12345678
+    }
+    fn version(&self) -> u16 {
+        0x0001
+    }
+    fn manufacturer(&self) -> u32 {
+        0x00000000
+    }
+    fn handle_interrupt(&mut self, processor: &mut Processor) {
+        let addr = processor.get_register(B);
+        processor.set_memory(addr, 0xBEEF);
+        processor.set_register(C, 0x1234);
+        processor.wait_cycles(300);
+    }
+}
+
+#[test]
+fn hardware_interrupt_mutates_processor() {
+    let mut machine = Processor::new();
+    machine.connect_hardware(TestDevice);
+    let program = assemble("SET B, 0x8000\nHWI 0").unwrap();
+    machine.memory.load_program(0x0000, &program);
+
+    machine.tick();
+    machine.tick();
+    machine.tick();
+    assert_eq!(machine.get_memory(0x8000), 0xBEEF);
+    assert_eq!(machine.get_register(C), 0x1234);
+    assert_eq!(machine.cycle_wait, 303);
+}
+
+#[test]
+fn clock_stores_ticks_in_c() {
+    let mut machine = Processor::new();
+    machine.connect_hardware(Clock::new());
+    let program =
+        assemble("SET A, 0\nSET B, 60\nHWI 0\nSET A, 1\n:loop HWI 0\nSET PC, loop").unwrap();
+    machine.memory.load_program(0x0000, &program);
+
+    // Run for just over two seconds
+    for _ in 0..PROCESSOR_FREQUENCY * 2 + 100 {
+        machine.tick();
+    }
+    assert_eq!(machine.get_register(C), 2);
+}
+
+#[test]
+fn keyboard_stores_keys_in_c() {
+    let mut machine = Processor::new();
+    machine.connect_hardware(Keyboard::new());
+    machine.with_hardware_mut(0, |keyboard: &mut Keyboard, _| {
+        keyboard.type_str("ab");
+        keyboard.press_key(keyboard::KEY_SHIFT);
+    });
+    let program = assemble(
+        "
+        SET A, 1
+        HWI 0
+        SET X, C
+        HWI 0
+        SET Y, C
+        HWI 0
+        SET Z, C
+        SET A, 2
+        SET B, 0x90
+        HWI 0
+        SET I, C
+        SET B, 0x91
+        HWI 0
+        SET J, C
+        :loop SET PC, loop
+        ",
+    )
+    .unwrap();
+    machine.memory.load_program(0x0000, &program);
+
+    for _ in 0..50 {
+        machine.tick();
+    }
+    assert_eq!(machine.get_register(X), 'a' as u16);
+    assert_eq!(machine.get_register(Y), 'b' as u16);
+    assert_eq!(machine.get_register(Z), 0);
+    assert_eq!(machine.get_register(I), 1);
+    assert_eq!(machine.get_register(J), 0);
+}
+
+#[test]
+fn clock_triggers_interrupts() {
+    let mut machine = Processor::new();
+    machine.connect_hardware(Clock::new());
+    let mut assembler = Assembler::new();
+    let program = assembler
+        .assemble(
+            "
+        IAS handler
+        SET A, 2
+        SET B, 0x42
+        HWI 0           ; Interrupt with message 0x42
+        SET A, 0
+        SET B, 6
+        HWI 0           ; Tick 10 times a second
+        :loop SET PC, loop
+        :handler
+        IFE A, 0x42
+            ADD [count], 1
+        RFI 0
+        :count DAT 0
+        ",
+        )
+        .unwrap();
+    machine.memory.load_program(0x0000, &program);
+
+    for _ in 0..PROCESSOR_FREQUENCY + 100 {
+        machine.tick();
+    }
+    let count = assembler.label("count").unwrap();
+    assert_eq!(machine.get_memory(count), 10);
+}
+
+#[test]
+fn keyboard_clears_buffer() {
+    let mut machine = Processor::new();
+    machine.connect_hardware(Keyboard::new());
+    machine.with_hardware_mut(0, |keyboard: &mut Keyboard, _| keyboard.type_str("abc"));
+    let program = assemble(
+        "
+        SET A, 1
+        HWI 0
+        SET X, C
+        SET A, 0
+        HWI 0
+        SET A, 1
+        HWI 0
+        SET Y, C
+        :loop SET PC, loop
+        ",
+    )
+    .unwrap();
+    machine.memory.load_program(0x0000, &program);
+
+    for _ in 0..30 {
+        machine.tick();
+    }
+    assert_eq!(machine.get_register(X), 'a' as u16);
+    assert_eq!(machine.get_register(Y), 0);
+    machine.with_hardware_mut(0, |keyboard: &mut Keyboard, _| {
+        assert_eq!(keyboard.buffer_len(), 0)
+    });
+}
+
+#[test]
+fn keyboard_triggers_interrupts() {
+    let mut machine = Processor::new();
+    machine.connect_hardware(Keyboard::new());
+    let program = assemble(
+        "
+        IAS handler
+        SET A, 3
+        SET B, 1
+        HWI 0
+        :loop SET PC, loop
+        :handler
+        SET A, 1
+        HWI 0
+        SET [0x1000], C
+        RFI 0
+        ",
+    )
+    .unwrap();
+    machine.memory.load_program(0x0000, &program);
+
+    for _ in 0..20 {
+        machine.tick();
+    }
+    machine.with_hardware_mut(0, |keyboard: &mut Keyboard, _| {
+        keyboard.type_key('q' as u16)
+    });
+    for _ in 0..20 {
+        machine.tick();
+    }
+    assert_eq!(machine.get_memory(0x1000), 'q' as u16);
+}
+
+// Images
+#[test]
+fn load_image_big_and_little_endian() {
+    let mut machine = Processor::new();
+    let words = machine
+        .load_image(0x1000, &[0x12, 0x34, 0xAB, 0xCD], Endianness::Big)
+        .unwrap();
+    assert_eq!(words, 2);
+    assert_eq!(machine.get_memory(0x1000), 0x1234);
+    assert_eq!(machine.get_memory(0x1001), 0xABCD);
+
+    machine
+        .load_image(0x1000, &[0x12, 0x34, 0xAB, 0xCD], Endianness::Little)
+        .unwrap();
+    assert_eq!(machine.get_memory(0x1000), 0x3412);
+    assert_eq!(machine.get_memory(0x1001), 0xCDAB);
+}
+
+#[test]
+fn load_image_errors() {
+    let mut machine = Processor::new();
+    match machine.load_image(0x0000, &[0x12, 0x34, 0x56], Endianness::Big) {
+        Err(ImageError::OddByteCount(3)) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+    match machine.load_image(0xFFFF, &[0x12, 0x34, 0x56, 0x78], Endianness::Big) {
+        Err(ImageError::TooLarge {
+            addr: 0xFFFF,
+            words: 2,
+        }) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+    assert_eq!(machine.get_memory(0xFFFF), 0x0000);
+    assert!(machine
+        .load_image(0xFFFF, &[0x12, 0x34], Endianness::Big)
+        .is_ok());
+}
+
+#[test]
+fn load_nyan_image_from_reader() {
+    let mut machine = Processor::new();
+    let mut bytes: &[u8] = include_bytes!("../progs/nyan.bin");
+    let words = machine
+        .memory_mut()
+        .load_reader(0x0000, &mut bytes, Endianness::Big)
+        .unwrap();
+    assert_eq!(words, 1603);
+    assert_eq!(machine.get_memory(0x0000), 0x1600);
+    assert_eq!(machine.disassemble(0x0000, 1)[0].text, "HWN Z");
+}
+
+#[test]
+fn save_and_reload_image() {
+    let mut machine = Processor::new();
+    machine.set_memory(0x0010, 0xBEEF);
+    machine.set_memory(0x0011, 0x0102);
+    let bytes = machine
+        .memory()
+        .dump_bytes(0x0010, 2, Endianness::Little)
+        .unwrap();
+    assert_eq!(bytes, vec![0xEF, 0xBE, 0x02, 0x01]);
+    assert!(machine
+        .memory()
+        .dump_bytes(0xFFFF, 2, Endianness::Little)
+        .is_err());
+
+    let path = std::env::temp_dir().join("dcpu16-rs-save-and-reload-image.bin");
+    machine
+        .memory()
+        .save_file(0x0010, 2, &path, Endianness::Big)
+        .unwrap();
+    let mut other = Processor::new();
+    other
+        .memory_mut()
+        .load_file(0x0100, &path, Endianness::Big)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(other.get_memory(0x0100), 0xBEEF);
+    assert_eq!(other.get_memory(0x0101), 0x0102);
+}
+
+#[test]
+fn load_program_files() {
+    let dir = std::env::temp_dir();
+    let source = dir.join(format!("dcpu16-load-{}.dasm", std::process::id()));
+    let image = dir.join(format!("dcpu16-load-{}.bin", std::process::id()));
+    let text = "SET A, 1\n:loop SET PC, loop\n";
+    std::fs::write(&source, text).unwrap();
+    std::fs::write(&image, [0x12, 0x34]).unwrap();
+
+    let mut memory = Memory::new();
+    let assembler = memory
+        .load_program_file(0x0000, &source, Endianness::Big)
+        .unwrap()
+        .unwrap();
+    assert_eq!(assembler.label("loop"), Some(0x0001));
+    let program = assemble(text).unwrap();
+    assert_eq!(&memory.words()[..3], &program.words()[..]);
+
+    match memory.load_program_file(0x0100, &source, Endianness::Big) {
+        Err(LoadError::MovedSource(0x0100)) => {}
+        other => panic!("Unexpected result {:?}", other.map(|_| ())),
+    }
+
+    let assembler = memory
+        .load_program_file(0x0100, &image, Endianness::Little)
+        .unwrap();
+    assert!(assembler.is_none());
+    assert_eq!(memory[0x0100], 0x3412);
+
+    std::fs::remove_file(&source).ok();
+    std::fs::remove_file(&image).ok();
+}
+
+// Errors
+#[test]
+fn step_reports_invalid_op_codes() {
+    let mut machine = Processor::new();
+    machine.set_memory(0x0000, 0x0018); // Unused basic op code
+    machine.set_memory(0x0001, 0x03e0); // Unused special op code
+    machine.set_memory(0x0002, 0x8401); // SET A, 0
+
+    assert_eq!(machine.step(), Err(ProcessorError::InvalidOpCode(0x18)));
+    assert_eq!(machine.get_register(PC), 0x0001);
+    assert_eq!(
+        machine.step(),
+        Err(ProcessorError::InvalidSpecialOpCode(0x1f))
+    );
+    assert_eq!(machine.get_register(PC), 0x0002);
+    assert_eq!(machine.step(), Ok(()));
+}
+
+#[test]
+fn tick_skips_invalid_instructions() {
+    let mut machine = Processor::new();
+    machine.set_memory(0x0000, 0x001c);
+    machine.set_memory(0x0001, 0x8c01); // SET A, 2
+    machine.tick();
+    machine.tick();
+    assert_eq!(machine.get_register(A), 0x0002);
+}
+
+#[test]
+fn invalid_value_and_register_codes() {
+    use std::convert::TryFrom;
+    assert_eq!(
+        Value::try_from(0x40).unwrap_err(),
+        ProcessorError::InvalidValue(0x40)
+    );
+    assert_eq!(
+        Register::try_from(0x08).unwrap_err(),
+        ProcessorError::InvalidRegister(0x08)
+    );
+    assert!(matches!(
+        Value::try_from(0x0A),
+        Ok(Value::RegisterPointer(C))
+    ));
+    assert!(matches!(Value::try_from(0x20), Ok(Value::Literal(0xFFFF))));
+}
+
+#[test]
+fn interrupt_queue_overflow_catches_fire() {
+    let mut machine = Processor::new();
+    let program = assemble(":loop SET PC, loop").unwrap();
+    machine.memory.load_program(0x0000, &program);
+    machine.set_register(IA, 0x1000);
+    machine.is_queuing_interrupts = true;
+    for i in 0..=256 {
+        machine.trigger_interrupt(i);
+    }
+
+    assert!(machine.is_on_fire());
+    assert_eq!(machine.step(), Err(ProcessorError::OnFire));
+}
+
+// Breakpoints
+fn load_source(source: &str) -> Processor {
+    let mut machine = Processor::new();
+    let program = assemble(source).unwrap();
+    machine.memory.load_program(0x0000, &program);
+    machine
+}
+
+/// A machine that runs `len` single cycle instructions to reach address `len`
+fn straight_line_machine(len: usize) -> Processor {
+    let mut machine = Processor::new();
+    let set_a = assemble("SET A, 1").unwrap().words()[0];
+    for addr in 0..len {
+        machine.memory[addr as u16] = set_a;
+    }
+    machine
+}
+
+#[test]
+fn run_until_stops_at_breakpoint() {
+    let mut machine = load_source(
+        "SET A, 1
+         SET B, 2
+         :loop SET PC, loop",
+    );
+    machine.add_breakpoint(0x0001);
+    assert_eq!(machine.run_until(100), StopReason::Breakpoint(0x0001));
+    assert_eq!(machine.get_register(A), 1);
+    assert_eq!(machine.get_register(B), 0);
+
+    // Resuming from a breakpoint runs past it
+    assert_eq!(machine.run_until(100), StopReason::CycleLimit);
+    assert_eq!(machine.get_register(B), 2);
+}
+
+#[test]
+fn breakpoint_at_end_of_run_still_stops() {
+    let mut machine = load_source(
+        "SET A, 1
+         SET B, 2
+         :loop SET PC, loop",
+    );
+    machine.add_breakpoint(0x0001);
+    machine.add_breakpoint(0x0000);
+    assert_eq!(machine.run_until(100), StopReason::Breakpoint(0x0000));
+    machine.remove_breakpoint(0x0000);
+
+    // SET A, 1 takes a single cycle, so the run ends right on the next breakpoint
+    assert_eq!(machine.run_until(1), StopReason::CycleLimit);
+    assert_eq!(machine.get_register(PC), 0x0001);
+    assert_eq!(machine.run_until(100), StopReason::Breakpoint(0x0001));
+    assert_eq!(machine.get_register(B), 0);
+
+    assert_eq!(machine.run_until(1), StopReason::CycleLimit);
+    assert_eq!(machine.get_register(B), 2);
+}
+
+#[test]
+fn run_until_stops_on_memory_write() {
+    let mut machine = load_source(
+        "SET [0x1000], 5
+         SET [0x2000], 6
+         :loop SET PC, loop",
+    );
+    machine.add_watchpoint(0x2000..=0x20ff, WatchKind::Write);
+    assert_eq!(
+        machine.run_until(100),
+        StopReason::MemoryWrite {
+            pc: 0x0002,
+            addr: 0x2000,
+            old: 0,
+            new: 6,
+        }
+    );
+    assert_eq!(machine.get_memory(0x1000), 5);
+}
+
+#[test]
+fn run_until_stops_on_memory_read() {
+    let mut machine = load_source(
+        "SET [0x3000], 7
+         SET A, [0x3000]
+         :loop SET PC, loop",
+    );
+    machine.add_watchpoint(0x3000..=0x3000, WatchKind::Read);
+    assert_eq!(
+        machine.run_until(100),
+        StopReason::MemoryRead {
+            pc: 0x0002,
+            addr: 0x3000,
+            value: 7,
+        }
+    );
+
+    assert!(machine.remove_watchpoint(0x3000));
+    assert_eq!(machine.run_until(100), StopReason::CycleLimit);
+}
+
+#[test]
+fn run_until_stops_on_register_change() {
+    let mut machine = load_source(
+        "SET A, 1
+         SET X, 3
+         :loop SET PC, loop",
+    );
+    machine.watch_register(X);
+    assert_eq!(
+        machine.run_until(100),
+        StopReason::RegisterChanged {
+            register: X,
+            old: 0,
+            new: 3,
+        }
+    );
+}
+
+#[test]
+fn run_until_reports_errors() {
+    let mut machine = Processor::new();
+    machine.set_memory(0x0000, 0x0000);
+    assert_eq!(
+        machine.run_until(100),
+        StopReason::Error(ProcessorError::InvalidSpecialOpCode(0x00))
+    );
+}
+
+// Snapshots
+fn snapshot_machine() -> Processor {
+    let mut machine = load_source(
+        "SET A, 0
+         SET B, 0x8000
+         HWI 0
+         SET A, 3
+         SET B, 0x000c
+         HWI 0
+         SET A, 0
+         SET B, 1
+         HWI 2
+         SET A, 2
+         SET B, 0x1234
+         HWI 2
+         IAS handler
+         :loop ADD [0x9000], 1
+         SET PC, loop
+         :handler ADD [0x9001], 1
+         RFI 0",
+    );
+    machine.connect_hardware(Monitor::new());
+    machine.connect_hardware(Keyboard::new());
+    machine.connect_hardware(Clock::new());
+    machine
+}
+
+#[test]
+fn snapshot_round_trips_through_bytes() {
+    let mut machine = snapshot_machine();
+    for _ in 0..1000 {
+        machine.tick();
+    }
+    machine.with_hardware_mut(1, |keyboard: &mut Keyboard, _| {
+        keyboard.type_str("hi");
+        keyboard.press_key(keyboard::KEY_SHIFT);
+    });
+
+    let snapshot = machine.snapshot().unwrap();
+    let bytes = snapshot.to_bytes();
+    assert_eq!(&bytes[..4], &SNAPSHOT_MAGIC);
+    assert_eq!(Snapshot::from_bytes(&bytes).unwrap(), snapshot);
+}
+
+#[test]
+fn restored_snapshot_runs_identically() {
+    let mut machine = snapshot_machine();
+    for _ in 0..1000 {
+        machine.tick();
+    }
+    let snapshot = Snapshot::from_bytes(&machine.snapshot().unwrap().to_bytes()).unwrap();
+    for _ in 0..5000 {
+        machine.tick();
+    }
+
+    let mut restored = snapshot_machine();
+    restored.restore(&snapshot).unwrap();
+    assert_eq!(restored.cycle(), 1000);
+    restored.with_hardware(0, |monitor: &Monitor, _| {
+        assert_eq!(monitor.screen_addr, 0x8000);
+        assert_eq!(monitor.border_color, 0x000c);
+    });
+    for _ in 0..5000 {
+        restored.tick();
+    }
+
+    // The clock ticks every 1666 cycles
+    assert_eq!(restored.get_memory(0x9001), 3);
+    assert_eq!(restored.snapshot().unwrap(), machine.snapshot().unwrap());
+}
+
+#[test]
+fn restore_rejects_mismatched_hardware() {
+    let snapshot = snapshot_machine().snapshot().unwrap();
+
+    let mut machine = Processor::new();
+    machine.connect_hardware(Monitor::new());
+    assert!(matches!(
+        machine.restore(&snapshot),
+        Err(SnapshotError::HardwareMismatch(_))
+    ));
+
+    let mut machine = Processor::new();
+    machine.connect_hardware(Clock::new());
+    machine.connect_hardware(Keyboard::new());
+    machine.connect_hardware(Monitor::new());
+    assert!(matches!(
+        machine.restore(&snapshot),
+        Err(SnapshotError::HardwareMismatch(0))
+    ));
+}
+
+#[test]
+fn invalid_snapshot_bytes() {
+    assert!(matches!(
+        Snapshot::from_bytes(b"nope"),
+        Err(SnapshotError::BadMagic)
+    ));
+
+    let mut bytes = Processor::new().snapshot().unwrap().to_bytes();
+    bytes[4] = 0xff;
+    assert!(matches!(
+        Snapshot::from_bytes(&bytes),
+        Err(SnapshotError::UnsupportedVersion(0x00ff))
+    ));
+
+    let bytes = Processor::new().snapshot().unwrap().to_bytes();
+    assert!(matches!(
+        Snapshot::from_bytes(&bytes[..100]),
+        Err(SnapshotError::Truncated)
+    ));
+}
+
+#[test]
+fn failed_restore_leaves_machine_unchanged() {
+    let mut machine = snapshot_machine();
+    for _ in 0..1000 {
+        machine.tick();
+    }
+    let mut snapshot = machine.snapshot().unwrap();
+    // The clock is the last device, so the monitor and keyboard load before it fails
+    snapshot.devices[2].data.truncate(3);
+
+    for _ in 0..1000 {
+        machine.tick();
+    }
+    machine.with_hardware_mut(0, |monitor: &mut Monitor, _| monitor.border_color = 0x0003);
+    machine.with_hardware_mut(1, |keyboard: &mut Keyboard, _| keyboard.type_str("hi"));
+    let before = machine.snapshot().unwrap();
+
+    assert!(matches!(
+        machine.restore(&snapshot),
+        Err(SnapshotError::Truncated)
+    ));
+    assert_eq!(machine.snapshot().unwrap(), before);
+
+    let mut snapshot = machine.snapshot().unwrap();
+    snapshot.memory.truncate(0x100);
+    assert!(matches!(
+        machine.restore(&snapshot),
+        Err(SnapshotError::MemorySize(0x100))
+    ));
+    assert_eq!(machine.snapshot().unwrap(), before);
+}
+
+#[test]
+fn truncated_device_state_is_ignored() {
+    let mut monitor = Monitor::new();
+    monitor.screen_addr = 0x8000;
+    let mut state = monitor.save_state();
+    monitor.screen_addr = 0x9000;
+    monitor.border_color = 0x0004;
+    state.truncate(5);
+
+    assert!(matches!(
+        monitor.load_state(&state, 1),
+        Err(SnapshotError::Truncated)
+    ));
+    assert_eq!(monitor.screen_addr, 0x9000);
+    assert_eq!(monitor.border_color, 0x0004);
+
+    let state = monitor.save_state();
+    assert!(matches!(
+        monitor.load_state(&state, 2),
+        Err(SnapshotError::InvalidDeviceState(0x7349f615))
+    ));
+
+    let mut clock = Clock::new();
+    let state = clock.save_state();
+    assert!(clock.load_state(&state[..6], 1).is_err());
+    assert_eq!(clock.save_state(), state);
+}
+
+// Journal
+fn journal_machine() -> Processor {
+    // Self-modifying: bumps the literal in its own `SET A, 0` every time around the loop
+    let mut machine = load_source(
+        "SET SP, 0
+         IAS handler
+         :loop SET A, 0
+         ADD [loop], 0x400
+         JSR sub
+         INT 7
+         SET PC, loop
+         :sub SET PUSH, A
+         ADD B, POP
+         SET PC, POP
+         :handler ADD [0x9000], A
+         RFI 0",
+    );
+    machine.enable_journal(DEFAULT_JOURNAL_CAPACITY);
+    machine
+}
+
+#[test]
+fn step_back_undoes_everything() {
+    let mut machine = journal_machine();
+    // Journal entries start on instruction boundaries
+    for _ in 0..97 {
+        machine.tick();
+    }
+    while machine.cycle_wait() > 0 {
+        machine.tick();
+    }
+    let snapshot = machine.snapshot().unwrap();
+    let count = machine.journal().unwrap().len();
+    for _ in 0..731 {
+        machine.tick();
+    }
+    assert_ne!(machine.snapshot().unwrap(), snapshot);
+
+    while machine.journal().unwrap().len() > count {
+        assert!(machine.step_back());
+    }
+    assert_eq!(machine.snapshot().unwrap(), snapshot);
+}
+
+#[test]
+fn step_back_reverts_self_modifying_code() {
+    let mut machine = journal_machine();
+    let original = machine.get_memory(0x0003);
+    for _ in 0..40 {
+        machine.tick();
+    }
+    assert_ne!(machine.get_memory(0x0003), original);
+
+    while machine.step_back() {}
+    assert_eq!(machine.get_memory(0x0003), original);
+    assert_eq!(machine.get_register(PC), 0x0000);
+    assert_eq!(machine.cycle(), 0);
+}
+
+#[test]
+fn run_back_to_address() {
+    let mut machine = journal_machine();
+    for _ in 0..40 {
+        machine.tick();
+    }
+    let sub = 0x000c;
+    assert!(machine.run_back_to(sub));
+    assert_eq!(machine.get_register(PC), sub);
+    assert_eq!(machine.journal().unwrap().last().unwrap().pc, 0x0007);
+
+    assert!(!machine.run_back_to(0x8000));
+    assert!(machine.journal().unwrap().is_empty());
+}
+
+#[test]
+fn journal_is_bounded() {
+    let mut machine = journal_machine();
+    machine.enable_journal(4);
+    for _ in 0..100 {
+        machine.tick();
+    }
+    assert_eq!(machine.journal().unwrap().len(), 4);
+    for _ in 0..4 {
+        assert!(machine.step_back());
+    }
+    assert!(!machine.step_back());
+}
+
+// Tracing
+fn trace_machine() -> (Processor, std::rc::Rc<std::cell::RefCell<Vec<TraceRecord>>>) {
+    let mut machine = load_source(
+        "SET A, 0x30
+         SET [0x8000], A
+         ADD A, [0x8000]
+         IFE A, 0x60
+         JSR sub
+         :loop SET PC, loop
+         :sub SET PC, POP",
+    );
+    let records = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let sink = records.clone();
+    machine.set_tracer(move |record: &TraceRecord| sink.borrow_mut().push(record.clone()));
+    (machine, records)
+}
+
+#[test]
+fn trace_records_operands_and_changes() {
+    let (mut machine, records) = trace_machine();
+    for _ in 0..20 {
+        machine.tick();
+    }
+
+    let records = records.borrow();
+    assert_eq!(records[0].cycle, 1);
+    assert_eq!(records[0].text, "SET A, 0x30");
+    assert_eq!(records[0].a, Some(0x30));
+    assert_eq!(records[0].b, None);
+    assert_eq!(records[0].registers, vec![(A, 0, 0x30), (PC, 0, 2)]);
+
+    assert_eq!(records[1].pc, 0x0002);
+    assert_eq!(records[1].memory, vec![(0x8000, 0, 0x30)]);
+
+    assert_eq!(records[2].text, "ADD A, [0x8000]");
+    assert_eq!(records[2].a, Some(0x30));
+    assert_eq!(records[2].b, Some(0x30));
+    assert_eq!(records[2].registers[0], (A, 0x30, 0x60));
+
+    assert_eq!(records[3].text, "IFE A, 0x60");
+    assert_eq!(records[3].b, Some(0x60));
+
+    assert_eq!(records[4].text, "JSR 0xc");
+    assert_eq!(records[4].a, Some(0x000c));
+    assert_eq!(records[4].memory, vec![(0xffff, 0, 0x000a)]);
+    assert_eq!(records[5].pc, 0x000c);
+    assert_eq!(records[6].pc, 0x000a);
+}
+
+#[test]
+fn text_trace_format() {
+    let (mut machine, records) = trace_machine();
+    for _ in 0..3 {
+        machine.tick();
+    }
+
+    let mut trace = TextTrace::new(vec![]);
+    for record in records.borrow().iter() {
+        trace.record(record);
+    }
+    let text = String::from_utf8(trace.into_inner()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        lines[0],
+        "         1 0000: SET A, 0x30              a=0030 b=---- A:0000->0030 PC:0000->0002"
+    );
+    assert_eq!(
+        lines[1],
+        "         3 0002: SET [0x8000], A          a=0030 b=---- PC:0002->0004 [8000]:0000->0030"
+    );
+}
+
+#[test]
+fn binary_trace_round_trips() {
+    let (mut machine, records) = trace_machine();
+    for _ in 0..20 {
+        machine.tick();
+    }
+
+    let mut trace = BinaryTrace::new(vec![]);
+    for record in records.borrow().iter() {
+        trace.record(record);
+    }
+    let bytes = trace.into_inner();
+    assert_eq!(&bytes[..4], &TRACE_MAGIC);
+    assert_eq!(read_binary_trace(&bytes).unwrap(), *records.borrow());
+    assert!(read_binary_trace(&bytes[..bytes.len() - 1]).is_err());
+}
+
+// GDB
+/// Sends each packet to a GDB stub and collects the replies, in no-ack mode
+fn gdb_client(port: u16, packets: Vec<&'static str>) -> Vec<String> {
+    use std::io::{Read, Write};
+
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut replies = vec![];
+    let mut no_ack = false;
+    for packet in packets {
+        let sum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(stream, "${}#{:02x}", packet, sum).unwrap();
+
+        let mut reply = vec![];
+        let mut byte = [0];
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'+' if reply.is_empty() => continue,
+                b'#' => break,
+                _ => reply.push(byte[0]),
+            }
+        }
+        let mut sum = [0; 2];
+        stream.read_exact(&mut sum).unwrap();
+        if !no_ack {
+            stream.write_all(b"+").unwrap();
+        }
+        no_ack |= packet == "QStartNoAckMode";
+        replies.push(String::from_utf8(reply[1..].to_vec()).unwrap());
+    }
+
+    replies
+}
+
+fn run_gdb_session(machine: &mut Processor, packets: Vec<&'static str>) -> Vec<String> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let client = std::thread::spawn(move || gdb_client(port, packets));
+
+    let (stream, _) = listener.accept().unwrap();
+    stream.set_nodelay(true).unwrap();
+    GdbStub::new(machine, stream).run().unwrap();
+    client.join().unwrap()
+}
+
+#[test]
+fn gdb_registers_and_memory() {
+    let mut machine = Processor::new();
+    machine.set_register(A, 0x1234);
+    machine.set_register(IA, 0xbeef);
+    machine.set_memory(0x8000, 0xabcd);
+
+    let replies = run_gdb_session(
+        &mut machine,
+        vec![
+            "qSupported:swbreak+",
+            "QStartNoAckMode",
+            "qXfer:features:read:target.xml:0,1000",
+            "g",
+            "p9",
+            "P1=0500",
+            "m8000,2",
+            "M8001,1:3412",
+            "D",
+        ],
+    );
+
+    assert!(replies[0].contains("qXfer:features:read+"));
+    assert_eq!(replies[1], "OK");
+    assert_eq!(replies[2], format!("l{}", TARGET_XML));
+    assert_eq!(replies[3], format!("3412{}efbe", "0000".repeat(10)));
+    assert_eq!(replies[4], "0000");
+    assert_eq!(replies[5], "OK");
+    assert_eq!(replies[6], "cdab0000");
+    assert_eq!(replies[7], "OK");
+    assert_eq!(replies[8], "OK");
+
+    assert_eq!(machine.get_register(B), 0x0005);
+    assert_eq!(machine.get_memory(0x8001), 0x1234);
+}
+
+#[test]
+fn gdb_breakpoints_step_and_continue() {
+    let mut machine = load_source(
+        "SET A, 1
+         SET B, 2
+         SET [0x9000], 3
+         :loop SET PC, loop",
+    );
+
+    let replies = run_gdb_session(
+        &mut machine,
+        vec![
+            "QStartNoAckMode",
+            "Z0,1,1",
+            "c",
+            "p9",
+            "s",
+            "p9",
+            "z0,1,1",
+            "Z2,9000,1",
+            "c",
+            "p1",
+            "k",
+        ],
+    );
+
+    assert_eq!(replies[1], "OK");
+    assert_eq!(replies[2], "T05swbreak:;");
+    assert_eq!(replies[3], "0100");
+    assert_eq!(replies[4], "S05");
+    assert_eq!(replies[5], "0200");
+    assert_eq!(replies[8], "T05watch:9000;");
+    assert_eq!(replies[9], "0200");
+    assert_eq!(machine.get_memory(0x9000), 3);
+}
+
+#[test]
+fn gdb_ignores_malformed_packets() {
+    let mut machine = Processor::new();
+    machine.set_register(A, 0x1234);
+
+    let replies = run_gdb_session(&mut machine, vec!["", "\u{e9}1", "p0", "D"]);
+
+    assert_eq!(replies, vec!["", "", "3412", "OK"]);
+}
+
+#[test]
+fn gdb_continue_stops_at_breakpoint_between_chunks() {
+    let mut machine = straight_line_machine(super::gdb::CONTINUE_CHUNK);
+
+    // The first chunk ends exactly on the breakpoint
+    let replies = run_gdb_session(
+        &mut machine,
+        vec!["QStartNoAckMode", "Z0,2710,1", "c", "p9", "k"],
+    );
+
+    assert_eq!(replies[2], "T05swbreak:;");
+    assert_eq!(replies[3], "1027");
+}
+
+// Debug adapter
+struct DapClient {
+    stream: std::io::BufReader<std::net::TcpStream>,
+    seq: u64,
+    events: Vec<super::json::Json>,
+}
+
+impl DapClient {
+    fn read(&mut self) -> super::json::Json {
+        use std::io::{BufRead, Read};
+
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.stream.read_line(&mut line).unwrap();
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            length = line["Content-Length:".len()..].trim().parse().unwrap();
+        }
+        let mut body = vec![0; length];
+        self.stream.read_exact(&mut body).unwrap();
+        super::json::Json::parse(std::str::from_utf8(&body).unwrap()).unwrap()
+    }
+
+    /// Sends a request and waits for its response, keeping any events that arrive first
+    fn request(&mut self, command: &str, arguments: &str) -> super::json::Json {
+        use std::io::Write;
+
+        self.seq += 1;
+        let body = format!(
+            r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+            self.seq, command, arguments
+        );
+        write!(
+            self.stream.get_mut(),
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+
+        loop {
+            let message = self.read();
+            if message.get("type").as_str() == Some("response") {
+                assert_eq!(message.get("request_seq").as_u64(), Some(self.seq));
+                return message;
+            }
+            self.events.push(message);
+        }
+    }
+
+    /// Waits for the next `stopped` event and returns its reason
+    fn stopped(&mut self) -> String {
+        loop {
+            let event = if self.events.is_empty() {
+                self.read()
+            } else {
+                self.events.remove(0)
+            };
+            if event.get("event").as_str() == Some("stopped") {
+                return event.get("body").get("reason").as_str().unwrap().to_owned();
+            }
+        }
+    }
+}
+
+/// Serves a debug adapter session to `client`, which runs on its own thread
+fn run_dap_session<F>(client: F)
+where
+    F: FnOnce(&mut DapClient) + Send + 'static,
+{
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let client = std::thread::spawn(move || {
+        let stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_nodelay(true).unwrap();
+        client(&mut DapClient {
+            stream: std::io::BufReader::new(stream),
+            seq: 0,
+            events: vec![],
+        });
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut server = DapServer::new(stream.try_clone().unwrap(), stream, || {
+        let mut machine = Processor::new();
+        machine.connect_hardware(Monitor::new());
+        machine
+    });
+    server.run().unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn assembler_maps_addresses_to_lines() {
+    let mut assembler = Assembler::new();
+    assembler
+        .assemble("; comment\nSET A, 1\n\n:loop\nSET PC, loop\nDAT 1, 2")
+        .unwrap();
+    assert_eq!(assembler.line_at(0x0000), Some(2));
+    assert_eq!(assembler.line_at(0x0001), Some(5));
+    assert_eq!(assembler.line_at(0x0002), Some(5));
+    assert_eq!(assembler.line_at(0x0004), Some(6));
+    assert_eq!(assembler.addr_of_line(2), Some((0x0000, 2)));
+    assert_eq!(assembler.addr_of_line(3), Some((0x0001, 5)));
+    assert_eq!(assembler.addr_of_line(7), None);
+}
+
+#[test]
+fn dap_session() {
+    let path = std::env::temp_dir().join(format!("dcpu16-dap-{}.dasm", std::process::id()));
+    std::fs::write(
+        &path,
+        "SET A, 1\nJSR sub\n:loop SET PC, loop\n\n:sub SET B, 2\nSET PC, POP\n",
+    )
+    .unwrap();
+    let program = path.to_string_lossy().replace('\\', "\\\\");
+
+    run_dap_session(move |client| {
+        let response = client.request("initialize", r#"{"adapterID":"dcpu"}"#);
+        assert_eq!(
+            response.get("body").get("supportsStepBack").as_bool(),
+            Some(true)
+        );
+        let launch = format!(r#"{{"program":"{}","stopOnEntry":true}}"#, program);
+        assert_eq!(
+            client.request("launch", &launch).get("success").as_bool(),
+            Some(true)
+        );
+
+        let breakpoints = format!(
+            r#"{{"source":{{"path":"{}"}},"breakpoints":[{{"line":4}},{{"line":99}}]}}"#,
+            program
+        );
+        let response = client.request("setBreakpoints", &breakpoints);
+        let breakpoints = response.get("body").get("breakpoints").as_array().to_vec();
+        assert_eq!(breakpoints[0].get("verified").as_bool(), Some(true));
+        assert_eq!(breakpoints[0].get("line").as_u64(), Some(5));
+        assert_eq!(breakpoints[1].get("verified").as_bool(), Some(false));
+
+        client.request("configurationDone", "{}");
+        assert_eq!(client.stopped(), "entry");
+        client.request("continue", r#"{"threadId":1}"#);
+        assert_eq!(client.stopped(), "breakpoint");
+
+        let response = client.request("stackTrace", r#"{"threadId":1}"#);
+        let frame = response.get("body").get("stackFrames").as_array()[0].clone();
+        assert_eq!(frame.get("line").as_u64(), Some(5));
+        assert_eq!(frame.get("name").as_str(), Some("sub: SET B, 2"));
+
+        let response = client.request("scopes", r#"{"frameId":0}"#);
+        let scopes = response.get("body").get("scopes").as_array().to_vec();
+        let names: Vec<&str> = scopes
+            .iter()
+            .map(|s| s.get("name").as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["Registers", "Stack", "Memory", "Hardware"]);
+
+        let response = client.request("variables", r#"{"variablesReference":1}"#);
+        let registers = response.get("body").get("variables").as_array().to_vec();
+        assert_eq!(registers[0].get("name").as_str(), Some("A"));
+        assert_eq!(registers[0].get("value").as_str(), Some("0x0001"));
+
+        let response = client.request("variables", r#"{"variablesReference":2}"#);
+        let stack = response.get("body").get("variables").as_array().to_vec();
+        assert_eq!(stack.len(), 1);
+        assert_eq!(stack[0].get("value").as_str(), Some("0x0003 @ 0xffff"));
+
+        let response = client.request("variables", r#"{"variablesReference":4}"#);
+        let hardware = response.get("body").get("variables").as_array().to_vec();
+        assert_eq!(
+            hardware[0].get("value").as_str(),
+            Some("id 0x7349f615, version 0x1802, manufacturer 0x1c6c8b36")
+        );
+
+        client.request("stepOut", r#"{"threadId":1}"#);
+        assert_eq!(client.stopped(), "step");
+        let response = client.request("stackTrace", r#"{"threadId":1}"#);
+        let frame = response.get("body").get("stackFrames").as_array()[0].clone();
+        assert_eq!(frame.get("line").as_u64(), Some(3));
+
+        client.request("stepBack", r#"{"threadId":1}"#);
+        assert_eq!(client.stopped(), "step");
+        client.request("next", r#"{"threadId":1}"#);
+        assert_eq!(client.stopped(), "step");
+
+        client.request("continue", r#"{"threadId":1}"#);
+        client.request("pause", r#"{"threadId":1}"#);
+        assert_eq!(client.stopped(), "pause");
+        client.request("disconnect", "{}");
+    });
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn dap_continue_stops_at_breakpoint_between_chunks() {
+    let path = std::env::temp_dir().join(format!("dcpu16-dap-chunk-{}.dasm", std::process::id()));
+    // The first chunk ends exactly on the breakpoint, and running past it hits an invalid
+    // instruction
+    let lines = super::dap::RUN_CHUNK;
+    let source = format!("{}SET B, 2\nDAT 0\n", "SET A, 1\n".repeat(lines));
+    std::fs::write(&path, source).unwrap();
+    let program = path.to_string_lossy().replace('\\', "\\\\");
+
+    run_dap_session(move |client| {
+        client.request("initialize", r#"{"adapterID":"dcpu"}"#);
+        let launch = format!(r#"{{"program":"{}","stopOnEntry":true}}"#, program);
+        client.request("launch", &launch);
+        let breakpoints = format!(
+            r#"{{"source":{{"path":"{}"}},"breakpoints":[{{"line":{}}}]}}"#,
+            program,
+            lines + 1
+        );
+        client.request("setBreakpoints", &breakpoints);
+        client.request("configurationDone", "{}");
+        assert_eq!(client.stopped(), "entry");
+
+        client.request("continue", r#"{"threadId":1}"#);
+        assert_eq!(client.stopped(), "breakpoint");
+        client.request("disconnect", "{}");
+    });
+    std::fs::remove_file(&path).ok();
+}
+
+// Decode cache
+
+fn assemble_word(source: &str) -> u16 {
+    assemble(source).unwrap().words()[0]
+}
+
+fn cached_machine(source: &str) -> Processor {
+    let mut machine = load_source(source);
+    machine.set_decode_cache(true);
+    machine
+}
+
+#[test]
+fn self_modifying_code_sees_its_own_writes() {
+    let source = format!(
+        "SET B, 0
+         :again ADD A, 1
+         IFE B, 1
+             SET PC, end
+         SET B, 1
+         SET [again], {}
+         SET PC, again
+         :end SET PC, end",
+        assemble_word("ADD A, 2")
+    );
+    for &cached in &[true, false] {
+        let mut machine = Processor::new();
+        machine.set_decode_cache(cached);
+        machine
+            .memory_mut()
+            .load_program(0x0000, &assemble(&source).unwrap());
+        for _ in 0..100 {
+            machine.tick();
+        }
+        assert_eq!(machine.get_register(A), 3);
+    }
+}
+
+#[test]
+fn decode_cache_sees_host_writes() {
+    assert!(!load_source("").is_decode_cached());
+    let mut machine = cached_machine("ADD A, 1\nSET PC, 0");
+    assert!(machine.is_decode_cached());
+    machine.step_instruction().unwrap();
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_register(A), 1);
+
+    machine.set_memory(0x0000, assemble_word("ADD A, 2"));
+    machine.step_instruction().unwrap();
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_register(A), 3);
+
+    machine.memory_mut()[0x0000] = assemble_word("ADD A, 3");
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_register(A), 6);
+}
+
+#[test]
+fn step_back_restores_decoded_instructions() {
+    let source = format!(
+        ":top ADD A, 1
+         SET [top], {}
+         SET PC, top",
+        assemble_word("ADD A, 2")
+    );
+    let mut machine = cached_machine(&source);
+    machine.enable_journal(100);
+    for _ in 0..4 {
+        machine.step_instruction().unwrap();
+    }
+    assert_eq!(machine.get_register(A), 3);
+    for _ in 0..3 {
+        assert!(machine.step_back());
+    }
+    assert_eq!(machine.get_register(PC), 0x0001);
+    assert_eq!(machine.get_register(A), 1);
+
+    // Skip the write, so the original instruction runs again
+    machine.set_register(PC, 0x0000);
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_register(A), 2);
+}
+
+#[test]
+fn snapshot_restore_clears_decode_cache() {
+    let mut machine = cached_machine("ADD A, 1\nSET PC, 0");
+    let snapshot = machine.snapshot().unwrap();
+    machine.set_memory(0x0000, assemble_word("ADD A, 2"));
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_register(A), 2);
+
+    machine.restore(&snapshot).unwrap();
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_register(A), 1);
+}
+
+// Batch runs
+
+const BATCH_SOURCE: &str = "
+        IAS handler
+        SET A, 0
+        SET B, 1
+        HWI 0
+        SET A, 2
+        HWI 0
+        SET I, 0
+:loop   ADD I, 1
+        MUL [0x1000+I], I
+        DIV J, 3
+        IFN I, 0
+            SET PC, loop
+        SET PC, loop
+:handler ADD X, 1
+        RFI 0
+";
+
+#[test]
+fn run_cycles_matches_ticking() {
+    let program = assemble(BATCH_SOURCE).unwrap();
+    let new_machine = |with_clock: bool| {
+        let mut machine = Processor::new();
+        if with_clock {
+            machine.connect_hardware(Clock::new());
+        }
+        machine.memory_mut().load_program(0x0000, &program);
+        machine
+    };
+
+    for &with_clock in &[false, true] {
+        let mut ticked = new_machine(with_clock);
+        let mut batched = new_machine(with_clock);
+        for &cycles in &[1, 2, 7, 1000, 12345, 50_000] {
+            for _ in 0..cycles {
+                ticked.tick();
+            }
+            let summary = batched.run_cycles(cycles);
+            assert_eq!(summary.cycles, cycles);
+            assert_eq!(summary.stop, StopReason::CycleLimit);
+            assert_eq!(ticked.snapshot().unwrap(), batched.snapshot().unwrap());
+        }
+        if with_clock {
+            assert!(batched.get_register(X) > 0);
+        }
+    }
+}
+
+#[test]
+fn run_cycles_counts_instructions() {
+    let mut machine = load_source(
+        "SET A, 1
+         ADD A, 1
+         DIV A, 2
+         :loop SET PC, loop",
+    );
+    // 1 + 2 + 3 cycles for the first three instructions
+    let summary = machine.run_cycles(6);
+    assert_eq!(summary.cycles, 6);
+    assert_eq!(summary.instructions, 3);
+    assert_eq!(machine.cycle(), 6);
+    assert_eq!(machine.get_register(PC), 0x0003);
+
+    // Stops partway through the wait after the jump
+    let summary = machine.run_cycles(3);
+    assert_eq!(summary.instructions, 2);
+    assert_eq!(machine.cycle_wait(), 1);
+}
+
+#[test]
+fn run_cycles_stops_early() {
+    let mut machine = load_source(
+        "SET A, 1
+         :here SET B, 2
+         DAT 0x0000",
+    );
+    machine.add_breakpoint(0x0001);
+    let summary = machine.run_cycles(100);
+    assert_eq!(summary.stop, StopReason::Breakpoint(0x0001));
+    assert_eq!(summary.cycles, 1);
+    assert_eq!(summary.instructions, 1);
+
+    let summary = machine.run_cycles(100);
+    assert_eq!(
+        summary.stop,
+        StopReason::Error(ProcessorError::InvalidSpecialOpCode(0))
+    );
+    assert_eq!(summary.cycles, 2);
+    assert_eq!(summary.instructions, 1);
+}
+
+#[test]
+fn run_until_cycle_and_run_for() {
+    let mut machine = load_source(":loop ADD A, 1\nSET PC, loop");
+    let summary = machine.run_until_cycle(1000);
+    assert_eq!(summary.cycles, 1000);
+    assert_eq!(machine.cycle(), 1000);
+    assert_eq!(machine.run_until_cycle(500).cycles, 0);
+
+    let summary = machine.run_for(std::time::Duration::from_millis(10));
+    assert_eq!(summary.cycles, PROCESSOR_FREQUENCY / 100);
+    assert_eq!(machine.cycle(), 1000 + PROCESSOR_FREQUENCY / 100);
+    assert_eq!(machine.get_register(A), (machine.cycle() / 4) as u16);
+}
+
+// Pacing
+
+#[derive(Default)]
+struct FakeClock {
+    now: std::time::Duration,
+    sleeps: usize,
+}
+
+impl HostClock for FakeClock {
+    fn now(&self) -> std::time::Duration {
+        self.now
+    }
+
+    fn sleep(&mut self, duration: std::time::Duration) {
+        self.now += duration;
+        self.sleeps += 1;
+    }
+}
+
+fn paced_machine() -> Processor {
+    load_source(":loop ADD A, 1\nSET PC, loop")
+}
+
+fn advance_by(pacer: &mut Pacer<FakeClock>, machine: &mut Processor, millis: u64) -> usize {
+    pacer.clock_mut().now += std::time::Duration::from_millis(millis);
+    pacer.advance(machine).cycles
+}
+
+#[test]
+fn pacer_runs_at_clock_rate() {
+    let mut machine = paced_machine();
+    let mut pacer = Pacer::with_clock(FakeClock::default());
+    assert_eq!(pacer.advance(&mut machine).cycles, 0);
+    assert_eq!(advance_by(&mut pacer, &mut machine, 10), 1000);
+    assert_eq!(advance_by(&mut pacer, &mut machine, 0), 0);
+    assert_eq!(advance_by(&mut pacer, &mut machine, 25), 2500);
+
+    pacer.set_frequency(1000);
+    assert_eq!(advance_by(&mut pacer, &mut machine, 50), 50);
+    assert_eq!(machine.cycle(), 3550);
+}
+
+#[test]
+fn pacer_speed_multipliers() {
+    let mut machine = paced_machine();
+    let mut pacer = Pacer::with_clock(FakeClock::default());
+    pacer.advance(&mut machine);
+
+    pacer.set_speed(Speed::Multiplier(0.5));
+    assert_eq!(advance_by(&mut pacer, &mut machine, 10), 500);
+    pacer.set_speed(Speed::Multiplier(2.0));
+    assert_eq!(advance_by(&mut pacer, &mut machine, 10), 2000);
+    pacer.set_speed(Speed::Unthrottled);
+    assert_eq!(advance_by(&mut pacer, &mut machine, 0), UNTHROTTLED_CHUNK);
+}
+
+#[test]
+fn pacer_limits_catching_up() {
+    let mut machine = paced_machine();
+    let mut pacer = Pacer::with_clock(FakeClock::default());
+    pacer.set_max_catch_up(std::time::Duration::from_millis(50));
+    pacer.advance(&mut machine);
+
+    assert_eq!(advance_by(&mut pacer, &mut machine, 2000), 5000);
+    assert_eq!(pacer.dropped_cycles(), 195_000);
+    assert_eq!(advance_by(&mut pacer, &mut machine, 10), 1000);
+
+    // Time spent paused is forgotten after a reset
+    pacer.clock_mut().now += std::time::Duration::from_secs(10);
+    pacer.reset();
+    assert_eq!(pacer.advance(&mut machine).cycles, 0);
+    assert_eq!(pacer.dropped_cycles(), 195_000);
+}
+
+#[test]
+fn pacer_calls_frame_callback() {
+    let mut machine = paced_machine();
+    let mut pacer = Pacer::with_clock(FakeClock::default());
+    let mut frames = 0;
+    let stop = pacer.run(&mut machine, |_| {
+        frames += 1;
+        frames < 60
+    });
+    assert_eq!(stop, None);
+    assert_eq!(frames, 60);
+    assert_eq!(pacer.clock().sleeps, 60);
+    assert!((99_990..=100_010).contains(&machine.cycle()));
+}
+
+#[test]
+fn pacer_stops_with_processor() {
+    let mut machine = paced_machine();
+    machine.add_breakpoint(0x0001);
+    let mut pacer = Pacer::with_clock(FakeClock::default());
+    let stop = pacer.run(&mut machine, |_| true);
+    assert_eq!(stop, Some(StopReason::Breakpoint(0x0001)));
+}
+
+#[test]
+fn pacer_stops_at_breakpoint_between_advances() {
+    let mut machine = paced_machine();
+    machine.add_breakpoint(0x0001);
+    let mut pacer = Pacer::with_clock(FakeClock::default());
+    pacer.set_frequency(1000);
+    pacer.advance(&mut machine);
+
+    // ADD A, 1 takes 2 cycles, so the first advance ends exactly on the breakpoint
+    pacer.clock_mut().now += std::time::Duration::from_millis(2);
+    assert_eq!(pacer.advance(&mut machine).stop, StopReason::CycleLimit);
+    assert_eq!(machine.get_register(PC), 0x0001);
+    pacer.clock_mut().now += std::time::Duration::from_millis(10);
+    let summary = pacer.advance(&mut machine);
+    assert_eq!(summary.stop, StopReason::Breakpoint(0x0001));
+    assert_eq!(summary.cycles, 0);
+}
+
+#[test]
+fn unthrottled_pacer_stops_at_breakpoint_between_chunks() {
+    let mut machine = straight_line_machine(UNTHROTTLED_CHUNK);
+    machine.add_breakpoint(UNTHROTTLED_CHUNK as u16);
+    let mut pacer = Pacer::with_clock(FakeClock::default());
+    pacer.set_speed(Speed::Unthrottled);
+
+    assert_eq!(pacer.advance(&mut machine).stop, StopReason::CycleLimit);
+    assert_eq!(
+        pacer.advance(&mut machine).stop,
+        StopReason::Breakpoint(UNTHROTTLED_CHUNK as u16)
+    );
+}
+
+// Cycle accounting
+
+/// Cycles for each basic opcode, straight from the spec
+const SPEC_BASIC_CYCLES: [(OpCode, usize); 27] = [
+    (SET, 1),
+    (ADD, 2),
+    (SUB, 2),
+    (MUL, 2),
+    (MLI, 2),
+    (DIV, 3),
+    (DVI, 3),
+    (MOD, 3),
+    (MDI, 3),
+    (AND, 1),
+    (BOR, 1),
+    (XOR, 1),
+    (SHR, 1),
+    (ASR, 1),
+    (SHL, 1),
+    (IFB, 2),
+    (IFC, 2),
+    (IFE, 2),
+    (IFN, 2),
+    (IFG, 2),
+    (IFA, 2),
+    (IFL, 2),
+    (IFU, 2),
+    (ADX, 3),
+    (SBX, 3),
+    (STI, 2),
+    (STD, 2),
+];
+
+const SPEC_SPECIAL_CYCLES: [(OpCode, usize); 9] = [
+    (JSR, 3),
+    (INT, 4),
+    (IAG, 1),
+    (IAS, 1),
+    (RFI, 3),
+    (IAQ, 2),
+    (HWN, 2),
+    (HWQ, 4),
+    (HWI, 4),
+];
+
+/// One value code for every operand mode, and the cycles it takes to look up
+const SPEC_VALUE_CYCLES: [(u16, usize); 13] = [
+    (0x00, 0),
+    (0x08, 0),
+    (0x10, 1),
+    (0x18, 0),
+    (0x19, 0),
+    (0x1a, 1),
+    (0x1b, 0),
+    (0x1c, 0),
+    (0x1d, 0),
+    (0x1e, 1),
+    (0x1f, 1),
+    (0x20, 0),
+    (0x3f, 0),
+];
+
+/// Runs one instruction made of `op`, `b` and `a`, with extra words of 0 for values that need
+/// them, returning the cycles it took and where PC ended up
+fn run_encoded(op: OpCode, b: u16, a: u16) -> (usize, u16) {
+    let mut machine = Processor::new();
+    machine.set_register(SP, 0x1000);
+    machine.set_memory(0x0000, op | b << 5 | a << 10);
+    machine.step_instruction().unwrap();
+    (machine.cycle(), machine.get_register(PC))
+}
+
+fn extra_words(value: u16) -> u16 {
+    match value {
+        0x10..=0x17 | 0x1a | 0x1e | 0x1f => 1,
+        _ => 0,
+    }
+}
+
+#[test]
+fn basic_opcode_cycles_match_spec() {
+    for &(op, op_cycles) in &SPEC_BASIC_CYCLES {
+        for &(a, a_cycles) in &SPEC_VALUE_CYCLES {
+            for &(b, b_cycles) in SPEC_VALUE_CYCLES.iter().filter(|&&(b, _)| b < 0x20) {
+                let expected = op_cycles + a_cycles + b_cycles;
+                let instruction = Instruction::from(op | b << 5 | a << 10);
+                assert_eq!(instruction.cycles() as usize, expected);
+
+                let (cycles, pc) = run_encoded(op, b, a);
+                let end = 1 + extra_words(a) + extra_words(b);
+                let is_skip = (0x10..=0x17).contains(&op) && pc != end;
+                let message = format!("op {:#04x}, b {:#04x}, a {:#04x}", op, b, a);
+                assert_eq!(cycles, expected + is_skip as usize, "{}", message);
+                if b != 0x1c {
+                    assert!(pc == end || (is_skip && pc == end + 1), "{}", message);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn special_opcode_cycles_match_spec() {
+    for &(op, op_cycles) in &SPEC_SPECIAL_CYCLES {
+        for &(a, a_cycles) in &SPEC_VALUE_CYCLES {
+            let expected = op_cycles + a_cycles;
+            let instruction = Instruction::from(op << 5 | a << 10);
+            assert_eq!(instruction.cycles() as usize, expected);
+
+            let (cycles, _) = run_encoded(SPL, op, a);
+            let message = format!("special op {:#04x}, a {:#04x}", op, a);
+            assert_eq!(cycles, expected, "{}", message);
+        }
+    }
+}
+
+#[test]
+fn invalid_opcodes_take_no_cycles() {
+    assert_eq!(Instruction::from(0x0018).cycles(), 0);
+    assert_eq!(Instruction::from(0x0000).cycles(), 0);
+    assert_eq!(Instruction::from(0x02e0).cycles(), 0);
+}
+
+#[test]
+fn failed_conditions_skip_chained_instructions() {
+    let mut machine = load_source(
+        "IFE A, 1
+         IFN [0x1000+B], 2
+         IFG PICK 1, 3
+         SET [0x2000+A], 0x1234
+         SET B, 1",
+    );
+    machine.step_instruction().unwrap();
+    // 2 for the test, 1 for failing and 1 for each chained conditional
+    assert_eq!(machine.cycle(), 5);
+    assert_eq!(machine.get_register(PC), 0x0008);
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_register(B), 1);
+    assert_eq!(machine.get_memory(0x2000), 0);
+}
+
+#[test]
+fn passed_conditions_cost_base_cycles() {
+    let mut machine = load_source("IFE [0x1000], 0\nSET A, 1");
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.cycle(), 3);
+    assert_eq!(machine.get_register(PC), 0x0002);
+}
+
+#[test]
+fn writes_to_stack_and_literals() {
+    let mut machine = load_source(
+        "SET PUSH, 1
+         SET PUSH, 2
+         SET PUSH, 3
+         SET PEEK, 4
+         SET PICK 2, 5
+         SET 0x1234, 6
+         SET A, 7",
+    );
+    for _ in 0..6 {
+        machine.step_instruction().unwrap();
+    }
+    assert_eq!(machine.get_memory(0xfffd), 4);
+    assert_eq!(machine.get_memory(0xffff), 5);
+    assert_eq!(machine.get_register(SP), 0xfffd);
+    assert_eq!(machine.cycle(), 1 + 1 + 1 + 1 + 2 + 2);
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_register(A), 7);
+}
+
+#[test]
+fn special_writes_read_next_word_once() {
+    let mut machine = load_source(
+        "IAS 0x55
+         IAG [0x1000]
+         HWN [0x1001]
+         IAG PICK 1
+         SET A, 1",
+    );
+    machine.set_register(SP, 0x2000);
+    machine.step_instruction().unwrap();
+    let cycle = machine.cycle();
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_memory(0x1000), 0x55);
+    assert_eq!(machine.cycle() - cycle, 2);
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_memory(0x1001), 0);
+    assert_eq!(machine.cycle() - cycle, 5);
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_memory(0x2001), 0x55);
+    machine.step_instruction().unwrap();
+    assert_eq!(machine.get_register(A), 1);
+}
+
+// Wraparound
+
+#[test]
+fn register_offset_wraps_around() {
+    let mut machine = load_source(
+        "SET B, [A+0xfff0]
+         ADD [A+0xfff0], 1
+         IFE [A+0xfff0], 8
+             SET C, 1
+         SET [A+0xfff1], 3",
+    );
+    machine.set_register(A, 0x0020);
+    machine.set_memory(0x0010, 7);
+    for _ in 0..5 {
+        machine.step_instruction().unwrap();
+    }
+    assert_eq!(machine.get_register(B), 7);
+    assert_eq!(machine.get_memory(0x0010), 8);
+    assert_eq!(machine.get_register(C), 1);
+    assert_eq!(machine.get_memory(0x0011), 3);
+}
+
+#[test]
+fn pick_wraps_around() {
+    let mut machine = load_source(
+        "SET A, PICK 0x20
+         ADD PICK 0x20, 1
+         IFE PICK 0x20, 10
+             SET C, 1
+         SET PICK 0x21, 3",
+    );
+    machine.set_register(SP, 0xfff0);
+    machine.set_memory(0x0010, 9);
+    for _ in 0..5 {
+        machine.step_instruction().unwrap();
+    }
+    assert_eq!(machine.get_register(A), 9);
+    assert_eq!(machine.get_memory(0x0010), 10);
+    assert_eq!(machine.get_register(C), 1);
+    assert_eq!(machine.get_memory(0x0011), 3);
+}
+
+#[test]
+fn load_program_wraps_around() {
+    let program = assemble("SET A, 1\nSET B, 0x1234\nSET C, 3").unwrap();
+    let mut machine = Processor::new();
+    machine.memory_mut().load_program(0xfffe, &program);
+    assert_eq!(machine.get_memory(0x0000), 0x1234);
+
+    machine.set_register(PC, 0xfffe);
+    for _ in 0..3 {
+        machine.step_instruction().unwrap();
+    }
+    assert_eq!(machine.get_register(A), 1);
+    assert_eq!(machine.get_register(B), 0x1234);
+    assert_eq!(machine.get_register(C), 3);
+    assert_eq!(machine.get_register(PC), 0x0002);
+}
+
+#[test]
+fn monitor_wraps_around() {
+    let mut machine = Processor::new();
+    let mut monitor = Monitor::new();
+    monitor.screen_addr = 0xff00;
+    monitor.palette_addr = 0xfff8;
+    // The 257th cell, using the 16th palette color
+    machine.set_memory(0x0000, 0xf041);
+    machine.set_memory(0x0007, 0x0fff);
+    let output = monitor.render_24bit_ansi(&machine);
+    assert!(output.contains("\x1b[38;2;240;240;240m"));
+}
+
+// Monitor
+fn monitor_machine(source: &str) -> Processor {
+    let mut machine = load_source(source);
+    machine.connect_hardware(Monitor::new());
+    machine
+}
+
+/// Runs `source` next to a monitor showing 0x8000 that isn't connected, so tests can render it
+/// directly
+fn screen_machine(source: &str) -> (Processor, Monitor) {
+    let mut monitor = Monitor::new();
+    monitor.screen_addr = 0x8000;
+    (load_source(source), monitor)
+}
+
+#[test]
+fn monitor_dumps_font_and_palette() {
+    let mut machine = monitor_machine(
+        "SET A, 4
+         SET B, 0x1000
+         HWI 0
+         SET A, 5
+         SET B, 0x2000
+         HWI 0
+         SUB PC, 1",
+    );
+    // SET, SET, HWI plus 256 dump cycles, then SET, SET, HWI plus 16 dump cycles
+    let summary = machine.run_cycles(1 + 2 + 4 + 256 + 1 + 2 + 4 + 16);
+    assert_eq!(summary.instructions, 6);
+    assert_eq!(machine.get_register(PC), 0x0008);
+
+    assert_eq!(machine.get_memory(0x1000), 0x000f);
+    assert_eq!(machine.get_memory(0x10ff), 0x7000);
+    assert_eq!(machine.get_memory(0x1100), 0x0000);
+    assert_eq!(machine.get_memory(0x2000), 0x0000);
+    assert_eq!(machine.get_memory(0x200f), 0x0fff);
+    assert_eq!(machine.get_memory(0x2006), 0x0a50);
+    assert_eq!(machine.get_memory(0x2010), 0x0000);
+}
+
+#[test]
+fn disconnected_monitor_is_blank() {
+    let mut machine = Processor::new();
+    let monitor = Monitor::new();
+    // Would be a white character on red if the screen read from address 0
+    machine.set_memory(0x0000, 0xf441);
+    assert!(!monitor.is_connected());
+    assert_eq!(monitor.get_cell(&machine, 0), None);
+
+    let output = monitor.render_24bit_ansi(&machine);
+    assert!(!output.contains("\x1b[38;2;240;240;240m"));
+    assert!(!output.contains("\x1b[48;2;160;0;0m"));
+}
+
+#[test]
+fn monitor_shows_splash_after_connecting() {
+    let mut machine = monitor_machine(
+        "SET A, 0
+         SET B, 0x8000
+         HWI 0
+         SUB PC, 1",
+    );
+    machine.set_memory(0x8000, 0xf041);
+    machine.run_cycles(1 + 2 + 4);
+    machine.with_hardware(0, |monitor: &Monitor, machine| {
+        assert!(monitor.is_connected());
+        assert!(monitor.is_starting_up(machine));
+        assert_ne!(monitor.get_cell(machine, 0), Some(0xf041));
+    });
+
+    machine.run_cycles(PROCESSOR_FREQUENCY);
+    machine.with_hardware(0, |monitor: &Monitor, machine| {
+        assert!(!monitor.is_starting_up(machine));
+        assert_eq!(monitor.get_cell(machine, 0), Some(0xf041));
+    });
+}
+
+// Framebuffer
+fn framebuffer_machine() -> (Processor, Monitor) {
+    let (mut machine, mut monitor) = screen_machine("");
+    monitor.font_addr = 0x9000;
+    monitor.palette_addr = 0x9100;
+    monitor.border_color = 0x2;
+    // Character 1 lights the top left pixel and the whole last column
+    machine.set_memory(0x9002, 0x0100);
+    machine.set_memory(0x9003, 0x00ff);
+    machine.set_memory(0x9100, 0x0000);
+    machine.set_memory(0x9101, 0x0f80);
+    machine.set_memory(0x9102, 0x000f);
+    (machine, monitor)
+}
+
+#[test]
+fn framebuffer_renders_custom_font_and_palette() {
+    let (mut machine, monitor) = framebuffer_machine();
+    // Second row, second column
+    machine.set_memory(0x8021, 0x1001);
+
+    let frame = monitor.render_framebuffer(&machine);
+    let border = DEFAULT_BORDER_SIZE;
+    assert_eq!(frame.width(), SCREEN_WIDTH + border * 2);
+    assert_eq!(frame.height(), SCREEN_HEIGHT + border * 2);
+    assert_eq!(frame.pixels().len(), frame.width() * frame.height() * 3);
+
+    let (left, top) = (border + CHAR_WIDTH, border + CHAR_HEIGHT);
+    assert_eq!(frame.get_pixel(left, top), [255, 136, 0]);
+    assert_eq!(frame.get_pixel(left, top + 1), [0, 0, 0]);
+    assert_eq!(frame.get_pixel(left + 3, top + 7), [255, 136, 0]);
+    assert_eq!(frame.get_pixel(left + 2, top + 7), [0, 0, 0]);
+    assert_eq!(frame.get_pixel(0, 0), [0, 0, 255]);
+    assert_eq!(frame.get_pixel(border - 1, border), [0, 0, 255]);
+    assert_eq!(frame.get_pixel(border, border), [0, 0, 0]);
+}
+
+#[test]
+fn framebuffer_blinks_with_emulated_time() {
+    let (mut machine, mut monitor) = framebuffer_machine();
+    monitor.border_size = 0;
+    machine.set_memory(0x8000, 0x1081);
+    machine.set_memory(0x0000, assemble_word("SUB PC, 1"));
+
+    assert_eq!(
+        monitor.render_framebuffer(&machine).get_pixel(0, 0),
+        [255, 136, 0]
+    );
+    machine.run_cycles(BLINK_CYCLES);
+    assert_eq!(
+        monitor.render_framebuffer(&machine).get_pixel(0, 0),
+        [0, 0, 0]
+    );
+    machine.run_cycles(BLINK_CYCLES);
+    assert_eq!(
+        monitor.render_framebuffer(&machine).get_pixel(0, 0),
+        [255, 136, 0]
+    );
+}
+
+#[test]
+fn disconnected_framebuffer_is_black() {
+    let (machine, mut monitor) = framebuffer_machine();
+    monitor.screen_addr = 0;
+    let frame = monitor.render_framebuffer(&machine);
+    assert!(frame.pixels().iter().all(|&channel| channel == 0));
+}
+
+// Screenshots
+fn test_frame() -> Framebuffer {
+    let mut frame = Framebuffer::new(3, 2);
+    frame.set_pixel(0, 0, [255, 0, 0]);
+    frame.set_pixel(1, 0, [0, 255, 0]);
+    frame.set_pixel(2, 1, [1, 2, 3]);
+    frame
+}
+
+#[test]
+fn ppm_round_trips() {
+    let frame = test_frame();
+    let mut bytes = vec![];
+    frame.write_ppm(&mut bytes).unwrap();
+    assert!(bytes.starts_with(b"P6\n3 2\n255\n"));
+    assert_eq!(Framebuffer::read_ppm(&mut &bytes[..]).unwrap(), frame);
+
+    let mut commented = b"P6 # made by hand\n3\t2 255\n".to_vec();
+    commented.extend_from_slice(frame.pixels());
+    assert_eq!(Framebuffer::read_ppm(&mut &commented[..]).unwrap(), frame);
+
+    assert!(Framebuffer::read_ppm(&mut &b"P3\n3 2\n255\n"[..]).is_err());
+    assert!(Framebuffer::read_ppm(&mut &bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn png_chunks_are_well_formed() {
+    let frame = test_frame();
+    let mut bytes = vec![];
+    frame.write_png(&mut bytes).unwrap();
+    assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+
+    // Walk the chunks, unpacking the stored deflate blocks in IDAT
+    let mut pos = 8;
+    let mut kinds = vec![];
+    let mut scanlines = vec![];
+    while pos < bytes.len() {
+        let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]);
+        let kind = &bytes[pos + 4..pos + 8];
+        let data = &bytes[pos + 8..pos + 8 + len as usize];
+        kinds.push(String::from_utf8(kind.to_vec()).unwrap());
+        if kind == b"IHDR" {
+            assert_eq!(data, &[0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        }
+        if kind == b"IDAT" {
+            assert_eq!(&data[..2], &[0x78, 0x01]);
+            assert_eq!(data[2], 1);
+            let stored = u16::from_le_bytes([data[3], data[4]]) as usize;
+            assert_eq!(u16::from_le_bytes([data[5], data[6]]), !(stored as u16));
+            scanlines.extend_from_slice(&data[7..7 + stored]);
+        }
+        pos += 12 + len as usize;
+    }
+    assert_eq!(kinds, vec!["IHDR", "IDAT", "IEND"]);
+    assert_eq!(
+        scanlines,
+        vec![0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]
+    );
+
+    // The CRC of an empty IEND chunk is well known
+    assert_eq!(&bytes[bytes.len() - 4..], &[0xae, 0x42, 0x60, 0x82]);
+}
+
+#[test]
+fn golden_mismatch_writes_diff() {
+    let dir = std::env::temp_dir().join(format!("dcpu16-golden-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let golden = dir.join("frame.ppm");
+    test_frame().save_file(&golden).unwrap();
+    assert_matches_golden(&test_frame(), &golden);
+
+    let mut changed = test_frame();
+    changed.set_pixel(1, 1, [9, 9, 9]);
+    let result = std::panic::catch_unwind(|| assert_matches_golden(&changed, &golden));
+    assert!(result.is_err());
+    assert!(dir.join("frame.actual.png").exists());
+    assert!(dir.join("frame.diff.png").exists());
+
+    let diff = diff_frames(&test_frame(), &changed).unwrap();
+    assert_eq!(diff.get_pixel(1, 1), [255, 0, 0]);
+    assert_eq!(diff.get_pixel(0, 0), [21, 21, 21]);
+    assert_eq!(diff_frames(&changed, &changed), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn nyan_frame_at(cycle: usize) -> Framebuffer {
+    let mut machine = Processor::new();
+    machine
+        .load_image(0x0000, include_bytes!("../progs/nyan.bin"), Endianness::Big)
+        .unwrap();
+    machine.connect_hardware(Monitor::new());
+    machine.run_until_cycle(cycle);
+
+    let mut frame = None;
+    machine.with_hardware(0, |monitor: &Monitor, machine| {
+        frame = Some(monitor.render_framebuffer(machine));
+    });
+    frame.unwrap()
+}
+
+#[test]
+fn nyan_matches_golden_frames() {
+    let golden = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+    for &cycle in &[50_000, 200_000, 250_000] {
+        let path = format!("{}/nyan_{}.ppm", golden, cycle);
+        assert_matches_golden(&nyan_frame_at(cycle), path);
+    }
+}
+
+// Blinking
+fn blink_machine() -> (Processor, Monitor) {
+    let (mut machine, monitor) = screen_machine("SUB PC, 1");
+    // A blinking white A on red, then a steady one
+    machine.set_memory(0x8000, 0xf4c1);
+    machine.set_memory(0x8001, 0xf441);
+    (machine, monitor)
+}
+
+#[test]
+fn blink_phase_follows_emulated_time() {
+    let (mut machine, monitor) = blink_machine();
+    assert!(monitor.is_blink_visible(&machine));
+    assert_eq!(monitor.get_cell_colors(&machine, 0xf4c1), (0xf, 0x4));
+
+    machine.run_cycles(BLINK_CYCLES - 1);
+    assert!(monitor.is_blink_visible(&machine));
+    machine.run_cycles(1);
+    assert!(!monitor.is_blink_visible(&machine));
+    assert_eq!(monitor.get_cell_colors(&machine, 0xf4c1), (0x4, 0x4));
+    assert_eq!(monitor.get_cell_colors(&machine, 0xf441), (0xf, 0x4));
+
+    machine.run_cycles(BLINK_CYCLES);
+    assert!(monitor.is_blink_visible(&machine));
+}
+
+#[test]
+fn ansi_renderers_blink() {
+    let (mut machine, monitor) = blink_machine();
+    let white_on_red = "\x1b[38;2;240;240;240m\x1b[48;2;160;0;0m";
+    let red_on_red = "\x1b[38;2;160;0;0m\x1b[48;2;160;0;0m";
+    let shown = monitor.render_24bit_ansi(&machine);
+    assert_eq!(shown.matches(white_on_red).count(), 2);
+    assert!(!shown.contains(red_on_red));
+    assert!(monitor
+        .render_ansi(&machine)
+        .contains("\x1b[38;5;231m\x1b[48;5;124m"));
+
+    machine.run_cycles(BLINK_CYCLES);
+    let hidden = monitor.render_24bit_ansi(&machine);
+    assert_eq!(hidden.matches(white_on_red).count(), 1);
+    assert_eq!(hidden.matches(red_on_red).count(), 1);
+    assert!(monitor
+        .render_ansi(&machine)
+        .contains("\x1b[38;5;124m\x1b[48;5;124m"));
+
+    // Rendering the same cycle always gives the same frame
+    assert_eq!(monitor.render_24bit_ansi(&machine), hidden);
+}
+
+// Border
+#[test]
+fn ansi_border_surrounds_screen() {
+    let (machine, mut monitor) = blink_machine();
+    monitor.border_color = 0x4;
+    assert_eq!(monitor.ansi_border_size(), (4, 2));
+
+    let output = monitor.render_24bit_ansi(&machine);
+    assert!(output.starts_with("\x1b[48;2;160;0;0m"));
+    let full_line = " ".repeat(SCREEN_WIDTH + 8);
+    for line in &[1, 2, 51, 52] {
+        assert!(output.contains(&format!("\x1b[{};1H{}", line, full_line)));
+    }
+    for line in 3..=50 {
+        assert!(output.contains(&format!("\x1b[{};1H    \x1b[{};133H    ", line, line)));
+    }
+    // Cells start inside the border
+    assert!(output.contains("\x1b[3;5H"));
+    assert!(output.contains("\x1b[47;5H"));
+    assert!(output.ends_with("\x1b[0m"));
+}
+
+#[test]
+fn ansi_border_repaints_on_color_change() {
+    let (machine, mut monitor) = blink_machine();
+    monitor.border_color = 0x4;
+    let red = monitor.render_ansi(&machine);
+    monitor.border_color = 0x1;
+    let blue = monitor.render_ansi(&machine);
+
+    assert!(red.starts_with("\x1b[48;5;124m"));
+    assert!(blue.starts_with("\x1b[48;5;19m"));
+    // Every border cell is drawn again, in the new color
+    let red_border = &red["\x1b[48;5;124m".len()..red.find("\x1b[3;5H").unwrap()];
+    let blue_border = &blue["\x1b[48;5;19m".len()..blue.find("\x1b[3;5H").unwrap()];
+    assert_eq!(red_border, blue_border);
+    assert_eq!(red_border.matches(";1H").count(), 52);
+}
+
+#[test]
+fn border_thickness_is_configurable() {
+    let (machine, mut monitor) = blink_machine();
+    monitor.border_color = 0x4;
+    monitor.border_size = 0;
+    assert_eq!(monitor.ansi_border_size(), (0, 0));
+    let output = monitor.render_24bit_ansi(&machine);
+    assert!(output.starts_with("\x1b[48;2;160;0;0m\x1b[1;1H\x1b[38;2;"));
+    let frame = monitor.render_framebuffer(&machine);
+    assert_eq!(
+        (frame.width(), frame.height()),
+        (SCREEN_WIDTH, SCREEN_HEIGHT)
+    );
+
+    monitor.border_size = 3;
+    assert_eq!(monitor.ansi_border_size(), (3, 2));
+    let output = monitor.render_24bit_ansi(&machine);
+    assert!(output.contains("\x1b[3;4H"));
+    assert!(output.contains(&format!("\x1b[52;1H{}", " ".repeat(SCREEN_WIDTH + 6))));
+    let frame = monitor.render_framebuffer(&machine);
+    assert_eq!(
+        (frame.width(), frame.height()),
+        (SCREEN_WIDTH + 6, SCREEN_HEIGHT + 6)
+    );
+    assert_eq!(frame.get_pixel(2, 50), [170, 0, 0]);
+    assert_eq!(frame.get_pixel(SCREEN_WIDTH + 3, 50), [170, 0, 0]);
+}
+
+// JSON
+use super::json::Json;
+
+#[test]
+fn json_string_escapes() {
+    let parsed = Json::parse(r#""a\"b\\c\/d\n\r\t\b\f\u0041\u00e9""#).unwrap();
+    assert_eq!(parsed, Json::from("a\"b\\c/d\n\r\t\u{8}\u{c}Aé"));
+    assert_eq!(
+        Json::from("a\"b\\c\n\u{1}é").to_string(),
+        r#""a\"b\\c\n\u0001é""#
+    );
+}
+
+#[test]
+fn json_surrogate_pairs() {
+    let parsed = Json::parse(r#""/src/\ud83d\ude00.dasm""#).unwrap();
+    assert_eq!(parsed.as_str(), Some("/src/\u{1f600}.dasm"));
+    assert_eq!(
+        Json::parse(r#""\uD83D\uDE00""#).unwrap().as_str(),
+        Some("\u{1f600}")
+    );
+    // Lone surrogates can't be represented, but don't swallow what follows
+    assert_eq!(
+        Json::parse(r#""\ud83dx""#).unwrap().as_str(),
+        Some("\u{fffd}x")
+    );
+    assert_eq!(
+        Json::parse(r#""\ud83d\u0041""#).unwrap().as_str(),
+        Some("\u{fffd}A")
+    );
+    assert_eq!(
+        Json::parse(r#""\ude00""#).unwrap().as_str(),
+        Some("\u{fffd}")
+    );
+}
+
+#[test]
+fn json_nesting() {
+    let text = r#" { "a" : [ 1, [ ], { }, { "b": [ true, false, null ] } ], "c": "d" } "#;
+    let parsed = Json::parse(text).unwrap();
+    assert_eq!(parsed.get("c").as_str(), Some("d"));
+    let a = parsed.get("a").as_array();
+    assert_eq!(a.len(), 4);
+    assert_eq!(a[0].as_u64(), Some(1));
+    assert_eq!(a[1], Json::Array(vec![]));
+    assert_eq!(a[2], Json::Object(vec![]));
+    assert_eq!(
+        a[3].get("b"),
+        &Json::Array(vec![Json::Bool(true), Json::Bool(false), Json::Null])
+    );
+    assert_eq!(parsed.get("missing"), &Json::Null);
+
+    let reparsed = Json::parse(&parsed.to_string()).unwrap();
+    assert_eq!(reparsed, parsed);
+    assert_eq!(
+        parsed.to_string(),
+        r#"{"a":[1,[],{},{"b":[true,false,null]}],"c":"d"}"#
+    );
+}
+
+#[test]
+fn json_numbers() {
+    let numbers = [
+        ("0", 0.0),
+        ("-0", 0.0),
+        ("42", 42.0),
+        ("-17", -17.0),
+        ("3.25", 3.25),
+        ("1e3", 1000.0),
+        ("1E+2", 100.0),
+        ("25e-2", 0.25),
+        ("-1.5e1", -15.0),
+    ];
+    for &(text, value) in &numbers {
+        assert_eq!(Json::parse(text), Some(Json::Number(value)), "{}", text);
+    }
+    assert_eq!(Json::parse("7").unwrap().as_u64(), Some(7));
+    assert_eq!(Json::parse("7.5").unwrap().as_u64(), None);
+    assert_eq!(Json::parse("-7").unwrap().as_u64(), None);
+    assert_eq!(Json::from(65535u16).to_string(), "65535");
+    assert_eq!(Json::Number(f64::NAN).to_string(), "null");
+}
+
+#[test]
+fn json_rejects_invalid_input() {
+    let invalid = [
+        "",
+        "nul",
+        "tru",
+        "[1,]",
+        "[1 2]",
+        "{\"a\" 1}",
+        "{\"a\":1,}",
+        "{a:1}",
+        "[",
+        "{",
+        "\"open",
+        "\"bad \\x escape\"",
+        "\"\\u12\"",
+        "\"\\u+123\"",
+        "\"raw\ttab\"",
+        "01",
+        "+1",
+        "1.",
+        ".5",
+        "1e",
+        "-",
+        "inf",
+        "NaN",
+        "1 2",
+        "[] []",
+    ];
+    for text in &invalid {
+        assert_eq!(Json::parse(text), None, "{:?} should be rejected", text);
+    }
+}
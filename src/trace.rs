@@ -0,0 +1,253 @@
+use super::disassembler::disassemble;
+use super::processor::Register;
+use super::snapshot::{StateReader, StateWriter};
+use std::fmt;
+use std::io::{self, Write};
+
+/// Every binary trace starts with these bytes
+pub const TRACE_MAGIC: [u8; 4] = *b"DCTR";
+
+/// Bumped whenever the binary trace layout changes
+pub const TRACE_VERSION: u16 = 1;
+
+/// One executed instruction
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceRecord {
+    /// The cycle the instruction executed on
+    pub cycle: usize,
+    pub pc: u16,
+    /// The instruction and its extra words
+    pub words: Vec<u16>,
+    pub text: String,
+    /// Value of the `a` operand
+    pub a: Option<u16>,
+    /// Value of the `b` operand, if the instruction read it
+    pub b: Option<u16>,
+    /// Registers that changed, as `(register, old, new)`
+    pub registers: Vec<(Register, u16, u16)>,
+    /// Memory that was written, as `(addr, old, new)`, in the order it was written
+    pub memory: Vec<(u16, u16, u16)>,
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operand = |value: Option<u16>| match value {
+            Some(value) => format!("{:04x}", value),
+            None => "----".to_owned(),
+        };
+        write!(
+            f,
+            "{:>10} {:04x}: {:<24} a={} b={}",
+            self.cycle,
+            self.pc,
+            self.text,
+            operand(self.a),
+            operand(self.b)
+        )?;
+        for &(register, old, new) in &self.registers {
+            write!(f, " {}:{:04x}->{:04x}", register, old, new)?;
+        }
+        for &(addr, old, new) in &self.memory {
+            write!(f, " [{:04x}]:{:04x}->{:04x}", addr, old, new)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Receives a record for every instruction executed while tracing is on.
+/// Any `FnMut(&TraceRecord)` closure can be used as a sink.
+pub trait TraceSink {
+    fn record(&mut self, record: &TraceRecord);
+}
+
+impl<F: FnMut(&TraceRecord)> TraceSink for F {
+    fn record(&mut self, record: &TraceRecord) {
+        self(record)
+    }
+}
+
+/// Writes one line of text per instruction
+pub struct TextTrace<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TextTrace<W> {
+    pub fn new(writer: W) -> TextTrace<W> {
+        TextTrace {
+            writer,
+            error: None,
+        }
+    }
+
+    /// The first error the writer returned, after which nothing more is written
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TraceSink for TextTrace<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_none() {
+            if let Err(err) = writeln!(self.writer, "{}", record) {
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+/// Writes a compact little-endian encoding of each record, which can be read back with
+/// `read_binary_trace`. The disassembly isn't stored since it can be rebuilt from the words.
+pub struct BinaryTrace<W: Write> {
+    writer: W,
+    has_header: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> BinaryTrace<W> {
+    pub fn new(writer: W) -> BinaryTrace<W> {
+        BinaryTrace {
+            writer,
+            has_header: false,
+            error: None,
+        }
+    }
+
+    /// The first error the writer returned, after which nothing more is written
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TraceSink for BinaryTrace<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let mut writer = StateWriter::new();
+        if !self.has_header {
+            self.has_header = true;
+            for &byte in &TRACE_MAGIC {
+                writer.write_u8(byte);
+            }
+            writer.write_u16(TRACE_VERSION);
+        }
+        writer.write_u64(record.cycle as u64);
+        writer.write_u16(record.pc);
+        writer.write_u8(record.words.len() as u8);
+        for &word in &record.words {
+            writer.write_u16(word);
+        }
+        let flags = record.a.is_some() as u8 | (record.b.is_some() as u8) << 1;
+        writer.write_u8(flags);
+        if let Some(a) = record.a {
+            writer.write_u16(a);
+        }
+        if let Some(b) = record.b {
+            writer.write_u16(b);
+        }
+        writer.write_u8(record.registers.len() as u8);
+        for &(register, old, new) in &record.registers {
+            writer.write_u8(register as u8);
+            writer.write_u16(old);
+            writer.write_u16(new);
+        }
+        writer.write_u16(record.memory.len() as u16);
+        for &(addr, old, new) in &record.memory {
+            writer.write_u16(addr);
+            writer.write_u16(old);
+            writer.write_u16(new);
+        }
+
+        if let Err(err) = self.writer.write_all(&writer.into_bytes()) {
+            self.error = Some(err);
+        }
+    }
+}
+
+fn invalid_trace(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Decodes a trace written by `BinaryTrace`
+pub fn read_binary_trace(bytes: &[u8]) -> io::Result<Vec<TraceRecord>> {
+    let mut records = vec![];
+    if bytes.is_empty() {
+        return Ok(records);
+    }
+
+    let truncated = |_| invalid_trace("trace is truncated");
+    let mut reader = StateReader::new(bytes);
+    for &byte in &TRACE_MAGIC {
+        if reader.read_u8().map_err(truncated)? != byte {
+            return Err(invalid_trace("not a binary trace"));
+        }
+    }
+    if reader.read_u16().map_err(truncated)? != TRACE_VERSION {
+        return Err(invalid_trace("unsupported trace version"));
+    }
+
+    while reader.remaining() > 0 {
+        let cycle = reader.read_u64().map_err(truncated)? as usize;
+        let pc = reader.read_u16().map_err(truncated)?;
+        let mut words = vec![];
+        for _ in 0..reader.read_u8().map_err(truncated)? {
+            words.push(reader.read_u16().map_err(truncated)?);
+        }
+        let flags = reader.read_u8().map_err(truncated)?;
+        let a = if flags & 1 != 0 {
+            Some(reader.read_u16().map_err(truncated)?)
+        } else {
+            None
+        };
+        let b = if flags & 2 != 0 {
+            Some(reader.read_u16().map_err(truncated)?)
+        } else {
+            None
+        };
+        let mut registers = vec![];
+        for _ in 0..reader.read_u8().map_err(truncated)? {
+            let index = reader.read_u8().map_err(truncated)? as usize;
+            let register = *Register::ALL
+                .get(index)
+                .ok_or_else(|| invalid_trace("invalid register"))?;
+            let old = reader.read_u16().map_err(truncated)?;
+            let new = reader.read_u16().map_err(truncated)?;
+            registers.push((register, old, new));
+        }
+        let mut memory = vec![];
+        for _ in 0..reader.read_u16().map_err(truncated)? {
+            let addr = reader.read_u16().map_err(truncated)?;
+            let old = reader.read_u16().map_err(truncated)?;
+            let new = reader.read_u16().map_err(truncated)?;
+            memory.push((addr, old, new));
+        }
+
+        let text = disassemble(&words, pc)
+            .first()
+            .map(|line| line.text.clone())
+            .unwrap_or_default();
+        records.push(TraceRecord {
+            cycle,
+            pc,
+            words,
+            text,
+            a,
+            b,
+            registers,
+            memory,
+        });
+    }
+
+    Ok(records)
+}
@@ -1,5 +1,7 @@
+use super::error::ProcessorError;
 use super::opcodes::OpCode;
 use super::processor::Register;
+use std::convert::TryFrom;
 
 #[derive(Copy, Clone, Debug)]
 pub enum Value {
@@ -27,14 +29,29 @@ impl Value {
     pub fn get_b(&self) -> u16 {
         self.to_u16() << 5
     }
+
+    /// Whether looking up the value reads the word after the instruction
+    pub fn uses_next_word(&self) -> bool {
+        match self {
+            Value::RegisterPointerOffset(_)
+            | Value::Pick
+            | Value::NextWordPointer
+            | Value::NextWord => true,
+            Value::Literal(_) => self.to_u16() == 0x1F,
+            _ => false,
+        }
+    }
 }
 
-impl From<u16> for Value {
-    fn from(value: u16) -> Value {
+impl Value {
+    /// Decodes the lowest 6 bits of `value`, which are always a valid value code
+    pub(crate) fn from_bits(value: u16) -> Value {
+        let value = value & 0x3f;
+        let register = Register::GENERAL[(value & 0x07) as usize];
         match value {
-            0x00..=0x07 => Value::Register(Register::from(value)),
-            0x08..=0x0f => Value::RegisterPointer(Register::from(value - 0x08)),
-            0x10..=0x17 => Value::RegisterPointerOffset(Register::from(value - 0x10)),
+            0x00..=0x07 => Value::Register(register),
+            0x08..=0x0f => Value::RegisterPointer(register),
+            0x10..=0x17 => Value::RegisterPointerOffset(register),
             0x18 => Value::Push,
             0x19 => Value::Peek,
             0x1A => Value::Pick,
@@ -43,8 +60,19 @@ impl From<u16> for Value {
             0x1D => Value::Register(Register::EX),
             0x1E => Value::NextWordPointer,
             0x1F => Value::NextWord,
-            0x20..=0x3f => Value::Literal(value.wrapping_sub(0x21)),
-            _ => panic!("Invalid value code: {}", value),
+            _ => Value::Literal(value.wrapping_sub(0x21)),
+        }
+    }
+}
+
+impl TryFrom<u16> for Value {
+    type Error = ProcessorError;
+
+    fn try_from(value: u16) -> Result<Value, ProcessorError> {
+        if value > 0x3f {
+            Err(ProcessorError::InvalidValue(value))
+        } else {
+            Ok(Value::from_bits(value))
         }
     }
 }
@@ -60,8 +88,9 @@ impl From<Value> for u16 {
             Value::Pick => 0x1A,
             Value::NextWordPointer => 0x1E,
             Value::NextWord => 0x1F,
-            Value::Literal(literal) if literal > 0x1E => 0x1F,
-            Value::Literal(literal) => literal + 0x21,
+            // -1 fits in a short literal too
+            Value::Literal(literal) if literal > 0x1E && literal != 0xFFFF => 0x1F,
+            Value::Literal(literal) => literal.wrapping_add(0x21),
             Value::OpCode(op) => op,
         }
     }
@@ -0,0 +1,79 @@
+use super::error::ProcessorError;
+use super::processor::Register;
+use std::ops::RangeInclusive;
+
+/// Which kinds of memory access a watchpoint stops on
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Stops execution when the guest accesses memory in `start..=end`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn new(range: RangeInclusive<u16>, kind: WatchKind) -> Watchpoint {
+        Watchpoint {
+            start: *range.start(),
+            end: *range.end(),
+            kind,
+        }
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        addr >= self.start && addr <= self.end
+    }
+
+    pub fn watches_reads(&self) -> bool {
+        self.kind != WatchKind::Write
+    }
+
+    pub fn watches_writes(&self) -> bool {
+        self.kind != WatchKind::Read
+    }
+}
+
+/// Why `Processor::run_until` or one of the batch runs stopped
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StopReason {
+    /// About to execute the instruction at this address
+    Breakpoint(u16),
+    /// The instruction at `pc` read from a watched address
+    MemoryRead {
+        pc: u16,
+        addr: u16,
+        value: u16,
+    },
+    /// The instruction at `pc` wrote to a watched address
+    MemoryWrite {
+        pc: u16,
+        addr: u16,
+        old: u16,
+        new: u16,
+    },
+    RegisterChanged {
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+    /// Ran for the requested number of cycles without stopping
+    CycleLimit,
+    Error(ProcessorError),
+}
+
+/// What happened during a batch run such as `Processor::run_cycles`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RunSummary {
+    /// Cycles that were executed, including ones spent waiting
+    pub cycles: usize,
+    /// Instructions that finished executing
+    pub instructions: usize,
+    pub stop: StopReason,
+}
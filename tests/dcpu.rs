@@ -0,0 +1,102 @@
+// Scripted sessions with the dcpu debugger binary
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+const PROGRAM: &str = "SET A, 1\nSET B, 2\n:loop ADD A, B\nSET PC, loop\n";
+
+/// Runs `dcpu` on `source` saved as `name`, typing `script` into it
+fn run_dcpu(name: &str, source: &str, args: &[&str], script: &str) -> Output {
+    let path = std::env::temp_dir().join(format!("dcpu16-{}-{}.dasm", name, std::process::id()));
+    std::fs::write(&path, source).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dcpu"))
+        .args(args)
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&path).ok();
+    output
+}
+
+/// Runs a debugger session that's expected to succeed, returning what it printed
+fn debugger_session(name: &str, source: &str, script: &str) -> String {
+    let output = run_dcpu(name, source, &[], script);
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn scripted_session() {
+    // An empty line repeats the previous command, and nothing after quit runs. The first
+    // continue stops straight away on the new breakpoint, the second runs round the loop.
+    let output = debugger_session(
+        "session",
+        PROGRAM,
+        "step\n\nbreak loop\ncontinue\ncontinue\nset x 0x10\nregisters\nset 0x100 42\n\
+         memory 0x100 2\nx 0 4\nfrobnicate\nstep 2x\nquit\nstep\n",
+    );
+    let expected = "\
+=> 0000: 8801           SET A, 1
+(dcpu) => 0001: 8c21           SET B, 2
+(dcpu) => 0002: 0402           ADD A, B
+(dcpu) Breakpoint set at 0x0002
+(dcpu) Breakpoint at 0x0002
+=> 0002: 0402           ADD A, B
+(dcpu) Breakpoint at 0x0002
+=> 0002: 0402           ADD A, B
+(dcpu) (dcpu) A=0003 B=0002 C=0000 X=0010 Y=0000 Z=0000 I=0000 J=0000
+SP=0000 PC=0002 EX=0000 IA=0000
+cycle=6 wait=0
+(dcpu) (dcpu) 0100: 002a 0000
+(dcpu)     0000: 8801           SET A, 1
+    0001: 8c21           SET B, 2
+:loop
+*=> 0002: 0402           ADD A, B
+    0003: 7f81 0002      SET PC, 2
+(dcpu) Unknown command 'frobnicate', try 'help'
+(dcpu) Invalid count '2x'
+(dcpu) ";
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn addresses_labels_and_registers() {
+    // Labels and registers can be used wherever an address is expected
+    let output = debugger_session(
+        "addresses",
+        PROGRAM,
+        "set B 7\nset 0x100 b\nm 0x100 1\nm b 1\nb loop\nb nowhere\nq\n",
+    );
+    assert_eq!(
+        output,
+        "=> 0000: 8801           SET A, 1\n\
+         (dcpu) (dcpu) (dcpu) 0100: 0007\n\
+         (dcpu) 0007: 0000\n\
+         (dcpu) Breakpoint set at 0x0002\n\
+         (dcpu) Unknown address or label 'nowhere'\n\
+         (dcpu) "
+    );
+}
+
+#[test]
+fn source_cannot_be_moved() {
+    // Labels and jumps are assembled for address 0, so the program would break anywhere else
+    let output = run_dcpu("moved", PROGRAM, &["--base", "0x100"], "quit\n");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("can't be loaded at 0x0100"),
+        "unexpected error {:?}",
+        stderr
+    );
+}